@@ -0,0 +1,309 @@
+use serde::{Serialize, Deserialize};
+use actix_web::{
+    body::MessageBody,
+    delete, get, patch, post,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header::AUTHORIZATION, Method},
+    middleware::{from_fn, Next},
+    web, Error, HttpResponse,
+};
+use chrono::offset::Utc;
+
+use crate::audit;
+use crate::errors::ApiError;
+use crate::boards::{page_offset, Boards, CurrentBoard};
+use crate::names::{fold, normalize};
+use crate::players::hash_token;
+use crate::ratelimit::RateLimit;
+use crate::store::{AuditFilter, AuditKind, AuditStore, Ban, BanMode, BanStore, BanTarget, EntryFilter, EntryStatus, EntryUpdate, StoreError};
+use crate::windows;
+
+// Only the hash of the admin key is kept, which is also what presented keys
+// are compared by, so the comparison leaks nothing about the key itself.
+pub struct AdminKey {
+    hash: String,
+}
+
+impl AdminKey {
+
+    pub fn new(key: &str) -> AdminKey {
+        AdminKey { hash: hash_token(key) }
+    }
+
+}
+
+// Rejects requests without `Authorization: Bearer <ADMIN_API_KEY>`. Without a
+// configured key the admin API is disabled altogether. Requests with a wrong
+// key and all changes made through the API are recorded in the audit log;
+// requests over the admin rate limit are turned down before they get here,
+// so guesses at the key can't flood the log.
+async fn require_admin(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(key) = req.app_data::<web::Data<AdminKey>>() else {
+        return Err(ApiError::forbidden("admin_disabled", "Admin API is disabled").into());
+    };
+    let presented = req.headers().get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let authorized = presented.is_some_and(|presented| hash_token(presented.trim()) == key.hash);
+    let audit_log = req.app_data::<web::Data<dyn AuditStore>>().cloned();
+    let action = format!("{} {}", req.method(), audit::truncate(req.path()));
+    let mut event = audit::request_event(req.request(), AuditKind::Admin, &action);
+    if !authorized {
+        event.reason = Some(String::from("Invalid admin key"));
+        if let Some(audit_log) = audit_log {
+            audit::record(audit_log.as_ref(), event).await;
+        }
+        return Err(ApiError::unauthorized("invalid_admin_key", "Invalid admin key").into());
+    }
+    let read_only = req.method() == Method::GET;
+    let response = next.call(req).await?;
+    if let (false, Some(audit_log)) = (read_only, audit_log) {
+        let status = response.status();
+        event.game = response.request().match_info().get("game").map(String::from);
+        event.accepted = status.is_success();
+        event.reason = (!status.is_success()).then(|| status.to_string());
+        audit::record(audit_log.as_ref(), event).await;
+    }
+    Ok(response)
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchQuery {
+    name: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    min_score: Option<i32>,
+    max_score: Option<i32>,
+    status: Option<EntryStatus>,
+    offset: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PageQuery {
+    offset: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuditQuery {
+    kind: Option<AuditKind>,
+    game: Option<String>,
+    name: Option<String>,
+    player_id: Option<String>,
+    ip: Option<String>,
+    accepted: Option<bool>,
+    from: Option<String>,
+    to: Option<String>,
+    offset: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Deleted {
+    deleted: u64,
+}
+
+fn no_such_entry() -> ApiError {
+    ApiError::not_found("not_found", "No such entry")
+}
+
+// Lists entries newest first, optionally only those of a name, submitted
+// between `from` and `to`, with a score between `min_score` and `max_score`
+// or with a status.
+#[get("/scores")]
+async fn search_scores(board: CurrentBoard, query: web::Query<SearchQuery>) -> Result<HttpResponse, ApiError> {
+    let range = windows::range(query.from.as_deref(), query.to.as_deref(), board.config.time_zone)?;
+    let filter = EntryFilter {
+        name: query.name.as_deref().map(normalize),
+        range,
+        min_score: query.min_score,
+        max_score: query.max_score,
+        status: query.status,
+    };
+    let limit = query.limit.unwrap_or(50).min(board.config.max_page_size);
+    Ok(HttpResponse::Ok().json(board.store.search(&filter, page_offset(query.offset)?, limit).await?))
+}
+
+#[get("/scores/{id}")]
+async fn get_score(board: CurrentBoard, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    match board.store.get(&id).await? {
+        Some(entry) => Ok(HttpResponse::Ok().json(entry)),
+        None => Err(no_such_entry()),
+    }
+}
+
+#[patch("/scores/{id}")]
+async fn edit_score(board: CurrentBoard, id: web::Path<String>, update: web::Json<EntryUpdate>) -> Result<HttpResponse, ApiError> {
+    let mut update = update.into_inner();
+    update.name = update.name.as_deref().map(normalize);
+    match board.store.update(&id, &update).await? {
+        Some(entry) => Ok(HttpResponse::Ok().json(entry)),
+        None => Err(no_such_entry()),
+    }
+}
+
+#[delete("/scores/{id}")]
+async fn delete_score(board: CurrentBoard, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    match board.store.delete(&id).await? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(no_such_entry()),
+    }
+}
+
+#[delete("/names/{name}/scores")]
+async fn delete_name(board: CurrentBoard, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let deleted = board.store.delete_by_name(&normalize(&name)).await?;
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+// The review queue: entries held for review, newest first.
+#[get("/reviews")]
+async fn list_reviews(board: CurrentBoard, page: web::Query<PageQuery>) -> Result<HttpResponse, ApiError> {
+    let filter = EntryFilter { status: Some(EntryStatus::Pending), ..EntryFilter::default() };
+    let limit = page.limit.unwrap_or(50).min(board.config.max_page_size);
+    Ok(HttpResponse::Ok().json(board.store.search(&filter, page_offset(page.offset)?, limit).await?))
+}
+
+async fn review(board: CurrentBoard, id: &str, status: EntryStatus) -> Result<HttpResponse, ApiError> {
+    match board.store.review(id, status).await? {
+        Some(entry) => Ok(HttpResponse::Ok().json(entry)),
+        None => Err(ApiError::not_found("not_found", "No pending entry with this id")),
+    }
+}
+
+#[post("/reviews/{id}/approve")]
+async fn approve_review(board: CurrentBoard, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    review(board, &id, EntryStatus::Published).await
+}
+
+#[post("/reviews/{id}/reject")]
+async fn reject_review(board: CurrentBoard, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    review(board, &id, EntryStatus::Rejected).await
+}
+
+#[derive(Deserialize, Debug)]
+struct NewBan {
+    target: BanTarget,
+    value: String,
+    mode: BanMode,
+    reason: Option<String>,
+}
+
+// Name bans match names by their folded form.
+fn ban_value(target: BanTarget, value: &str) -> String {
+    match target {
+        BanTarget::Name => fold(value),
+        BanTarget::Player | BanTarget::Ip => value.trim().to_string(),
+    }
+}
+
+// Shadowing applies to the entries already submitted under a name, by a
+// player or from an address on all boards. `value` is that of the ban, so
+// names match by their folded form like new submissions do. Entries stored
+// before their address was kept can't be traced back to it.
+async fn shadow_entries(boards: &Boards, target: BanTarget, value: &str, shadow: bool) -> Result<(), StoreError> {
+    for board in boards.iter() {
+        match target {
+            BanTarget::Name => for name in board.store.names().await? {
+                if fold(&name) == value {
+                    board.store.shadow_name(&name, shadow).await?;
+                }
+            },
+            BanTarget::Player => {
+                board.store.shadow_player(value, shadow).await?;
+            },
+            BanTarget::Ip => {
+                board.store.shadow_ip(&hash_token(value), shadow).await?;
+            },
+        }
+    }
+    Ok(())
+}
+
+#[get("/bans")]
+async fn list_bans(bans: web::Data<dyn BanStore>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(bans.list().await?))
+}
+
+#[post("/bans")]
+async fn add_ban(bans: web::Data<dyn BanStore>, boards: web::Data<Boards>, new: web::Json<NewBan>) -> Result<HttpResponse, ApiError> {
+    let new = new.into_inner();
+    let ban = Ban {
+        target: new.target,
+        value: ban_value(new.target, &new.value),
+        mode: new.mode,
+        reason: new.reason,
+        created: Utc::now(),
+    };
+    if ban.value.is_empty() {
+        return Err(ApiError::bad_request("invalid_request", "Ban value is empty"));
+    }
+    bans.add(ban.clone()).await?;
+    if ban.mode == BanMode::Shadow {
+        shadow_entries(&boards, ban.target, &ban.value, true).await?;
+    }
+    Ok(HttpResponse::Created().json(ban))
+}
+
+#[delete("/bans/{target}/{value}")]
+async fn remove_ban(bans: web::Data<dyn BanStore>, boards: web::Data<Boards>, path: web::Path<(BanTarget, String)>) -> Result<HttpResponse, ApiError> {
+    let (target, value) = path.into_inner();
+    let ban = bans.remove(target, &ban_value(target, &value)).await?
+        .ok_or_else(|| ApiError::not_found("not_found", "No such ban"))?;
+    // Entries of the lifted ban may still be covered by other shadow-bans,
+    // which are applied again.
+    if ban.mode == BanMode::Shadow {
+        shadow_entries(&boards, ban.target, &ban.value, false).await?;
+        for other in bans.list().await?.iter().filter(|other| other.mode == BanMode::Shadow) {
+            shadow_entries(&boards, other.target, &other.value, true).await?;
+        }
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Audit events newest first, filtered by kind, game, name, player_id, ip,
+// accepted and the time range between `from` and `to` in UTC.
+#[get("/audit")]
+async fn search_audit(audit_log: web::Data<dyn AuditStore>, query: web::Query<AuditQuery>) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let range = windows::range(query.from.as_deref(), query.to.as_deref(), chrono_tz::UTC)?;
+    let filter = AuditFilter {
+        kind: query.kind,
+        game: query.game,
+        name: query.name,
+        player_id: query.player_id,
+        ip: query.ip,
+        accepted: query.accepted,
+        range,
+    };
+    let limit = query.limit.unwrap_or(50).min(1000);
+    Ok(HttpResponse::Ok().json(audit_log.search(&filter, page_offset(query.offset)?, limit).await?))
+}
+
+fn board_admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(search_scores)
+        .service(get_score)
+        .service(edit_score)
+        .service(delete_score)
+        .service(delete_name)
+        .service(list_reviews)
+        .service(approve_review)
+        .service(reject_review);
+}
+
+// Like the public routes, the admin routes of the default board are also
+// served without /games/{game}. Bans apply to all boards.
+pub fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(from_fn(require_admin))
+            .wrap(RateLimit::admin())
+            .service(list_bans)
+            .service(add_ban)
+            .service(remove_ban)
+            .service(search_audit)
+            .configure(board_admin_routes)
+            .service(web::scope("/games/{game}").configure(board_admin_routes))
+    );
+}
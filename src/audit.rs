@@ -0,0 +1,48 @@
+use actix_web::{http::header::{HeaderName, USER_AGENT}, web, HttpRequest};
+use chrono::offset::Utc;
+
+use crate::ratelimit::RateLimits;
+use crate::store::{AuditEvent, AuditKind, AuditStore};
+
+const CLIENT_VERSION: HeaderName = HeaderName::from_static("x-client-version");
+
+// Long header values are cut off, so the log can't be flooded through them.
+const MAX_FIELD_LENGTH: usize = 200;
+
+pub fn truncate(value: &str) -> String {
+    value.chars().take(MAX_FIELD_LENGTH).collect()
+}
+
+fn header(req: &HttpRequest, name: &HeaderName) -> Option<String> {
+    req.headers().get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(truncate)
+}
+
+// An event for `req` with the client's address, user agent and the version
+// it sends in X-Client-Version filled in, and nothing else.
+pub fn request_event(req: &HttpRequest, kind: AuditKind, action: &str) -> AuditEvent {
+    let ip = req.app_data::<web::Data<RateLimits>>().and_then(|limits| limits.client_ip(req));
+    AuditEvent {
+        time: Utc::now(),
+        kind,
+        game: None,
+        action: action.to_string(),
+        accepted: false,
+        reason: None,
+        entry_id: None,
+        name: None,
+        player_id: None,
+        score: None,
+        ip,
+        user_agent: header(req, &USER_AGENT),
+        client_version: header(req, &CLIENT_VERSION),
+    }
+}
+
+// Failing to write the log doesn't fail the request it records.
+pub async fn record(audit: &dyn AuditStore, event: AuditEvent) {
+    if let Err(err) = audit.record(event).await {
+        eprintln!("Could not write audit event: {}", err);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Deserialize;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use chrono::offset::Utc;
+use chrono_tz::Tz;
+
+use crate::config::{BoardSettings, Config};
+use crate::errors::ApiError;
+use crate::ratelimit::{Limit, Limiter};
+use crate::replays::{ReplayVerifier, SubprocessVerifier};
+use crate::rules::Rules;
+use crate::signing::Keyring;
+use crate::store::{ScoreQuery, ScoreStore, SortOrder};
+use crate::windows::{self, WindowError};
+
+pub struct BoardConfig {
+    pub sort: SortOrder,
+    pub keyring: Keyring,
+    pub rules: Rules,
+    pub max_page_size: u64,
+    // Calendar windows (daily, weekly, ...) start at midnight in this zone.
+    pub time_zone: Tz,
+    // Rank each name only by its best entry unless a request asks otherwise.
+    pub best_per_player: bool,
+    // Whether scores can be submitted without a player token.
+    pub allow_anonymous: bool,
+    // Checks the replays submitted with scores before they are published.
+    pub verifier: Option<Box<dyn ReplayVerifier>>,
+    pub require_replay: bool,
+    // In bytes, before compression.
+    pub max_replay_size: usize,
+}
+
+impl BoardConfig {
+
+    // Checks the settings of a board. Boards without their own keys use the
+    // global score keys, and without their own time zone the global one or
+    // UTC. The replay verifier is the command of a headless verifier.
+    pub fn new(name: &str, settings: &BoardSettings, config: &Config) -> Result<BoardConfig, String> {
+        let invalid = |err: String| format!("Board {}: {}", name, err);
+        if settings.max_page_size == 0 {
+            return Err(invalid(String::from("max_page_size should be at least 1")));
+        }
+        let sort = match settings.sort.as_str() {
+            "desc" => SortOrder::Descending,
+            "asc" => SortOrder::Ascending,
+            other => return Err(invalid(format!("sort should be asc or desc, not {}", other))),
+        };
+        // Rates are points earned per second, which says nothing about e.g.
+        // completion times; min_score catches implausibly fast ones.
+        if sort == SortOrder::Ascending && (settings.max_score_rate.is_some() || settings.suspicious_score_rate.is_some()) {
+            return Err(invalid(String::from("max_score_rate and suspicious_score_rate only apply to desc boards")));
+        }
+        let keys = settings.keys.as_ref().or(config.score_keys.as_ref())
+            .ok_or_else(|| invalid(String::from("keys or the global score keys should be set")))?;
+        let keyring = Keyring::parse(keys).map_err(|err| invalid(format!("keys: {}", err)))?;
+        let submissions = match settings.max_submissions_per_hour {
+            Some(max) => Some(format!("{}/3600", max).parse::<Limit>().map_err(|err| invalid(format!("max_submissions_per_hour: {}", err)))?),
+            None => None,
+        };
+        let rules = Rules {
+            min_score: Some(settings.min_score),
+            max_score: settings.max_score,
+            max_score_rate: settings.max_score_rate,
+            suspicious_score: settings.suspicious_score,
+            suspicious_score_rate: settings.suspicious_score_rate,
+            submissions: submissions.map(Limiter::new),
+        };
+        let time_zone = match settings.time_zone.as_ref().or(config.time_zone.as_ref()) {
+            Some(zone) => zone.parse().map_err(|_| invalid(format!("Unknown time zone {}", zone)))?,
+            None => Tz::UTC,
+        };
+        let verifier: Option<Box<dyn ReplayVerifier>> = match &settings.replay_verifier {
+            Some(command) => Some(Box::new(SubprocessVerifier::parse(command, Duration::from_secs(settings.replay_verifier_timeout))
+                .map_err(|err| invalid(format!("replay_verifier: {}", err)))?)),
+            None => None,
+        };
+        Ok(BoardConfig {
+            sort,
+            keyring,
+            rules,
+            max_page_size: settings.max_page_size,
+            time_zone,
+            best_per_player: settings.best_per_player,
+            allow_anonymous: settings.allow_anonymous,
+            verifier,
+            require_replay: settings.require_replay,
+            max_replay_size: settings.max_replay_size,
+        })
+    }
+
+}
+
+pub struct Board {
+    pub name: String,
+    pub config: BoardConfig,
+    pub store: Arc<dyn ScoreStore>,
+}
+
+// Query parameters shared by the routes reading a board: `from` and `to` for
+// custom durations, and `best` to override the board's best-per-player mode.
+#[derive(Deserialize, Debug)]
+pub struct BoardQuery {
+    from: Option<String>,
+    to: Option<String>,
+    best: Option<bool>,
+}
+
+impl Board {
+
+    // What a request for a {duration} of this board currently ranks, as seen
+    // by the player with the id `viewer`.
+    pub fn query(&self, duration: &str, query: &BoardQuery, viewer: Option<String>) -> Result<ScoreQuery, WindowError> {
+        let range = windows::resolve(duration, query.from.as_deref(), query.to.as_deref(), self.config.time_zone, Utc::now())?;
+        Ok(ScoreQuery {
+            range,
+            best_per_player: query.best.unwrap_or(self.config.best_per_player),
+            viewer,
+        })
+    }
+
+}
+
+// The offset of a page, which SQLite and Mongo take as a signed 64 bit
+// number.
+pub fn page_offset(offset: Option<u64>) -> Result<u64, ApiError> {
+    match offset {
+        Some(offset) if offset > i64::MAX as u64 => Err(ApiError::bad_request("invalid_offset", "offset is too large")),
+        offset => Ok(offset.unwrap_or(0)),
+    }
+}
+
+// Board names end up in URLs, collection and table names.
+pub fn valid_board_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 32
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub struct Boards {
+    boards: HashMap<String, Arc<Board>>,
+    default: String,
+}
+
+impl Boards {
+
+    pub fn new(default: &str) -> Boards {
+        Boards { boards: HashMap::new(), default: default.to_string() }
+    }
+
+    pub fn insert(&mut self, board: Board) {
+        self.boards.insert(board.name.clone(), Arc::new(board));
+    }
+
+    // Routes without a {game} segment use the default board.
+    pub fn get(&self, name: Option<&str>) -> Option<Arc<Board>> {
+        self.boards.get(name.unwrap_or(&self.default)).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Board>> {
+        self.boards.values()
+    }
+
+}
+
+// Extracts the board a request is for from the {game} path segment.
+pub struct CurrentBoard(Arc<Board>);
+
+impl Deref for CurrentBoard {
+    type Target = Board;
+
+    fn deref(&self) -> &Board {
+        &self.0
+    }
+}
+
+impl FromRequest for CurrentBoard {
+    type Error = ApiError;
+    type Future = Ready<Result<CurrentBoard, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let board = req.app_data::<web::Data<Boards>>()
+            .and_then(|boards| boards.get(req.match_info().get("game")));
+        ready(match board {
+            Some(board) => Ok(CurrentBoard(board)),
+            None => Err(ApiError::not_found("unknown_game", "Unknown game")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_score_rates_on_ascending_boards() {
+        let config = Config { score_keys: Some(String::from("k:secret")), ..Config::default() };
+        let rated = BoardSettings { max_score_rate: Some(10.0), ..BoardSettings::default() };
+        assert!(BoardConfig::new("gurtle", &rated, &config).is_ok());
+        let times = BoardSettings { sort: String::from("asc"), ..rated };
+        assert!(BoardConfig::new("gurtle", &times, &config).is_err());
+        let times = BoardSettings { max_score_rate: None, suspicious_score_rate: Some(5.0), ..times };
+        assert!(BoardConfig::new("gurtle", &times, &config).is_err());
+    }
+
+}
@@ -0,0 +1,499 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Serialize, Deserialize};
+
+// Settings are layered: defaults, then the config file, then environment
+// variables, then command line flags, each overriding the ones before.
+#[derive(Parser, Debug)]
+#[command(version, about = "Leaderboard server for gurtle")]
+pub struct Cli {
+    #[arg(long, short, value_name = "FILE", help = "TOML config file")]
+    pub config: Option<PathBuf>,
+    #[arg(long, help = "Address to listen on")]
+    pub bind: Option<String>,
+    #[arg(long, help = "Port to listen on")]
+    pub port: Option<u16>,
+    #[arg(long, value_enum, help = "Storage backend")]
+    pub storage: Option<Backend>,
+    #[arg(long, value_name = "URI", help = "MongoDB connection string")]
+    pub mongo_uri: Option<String>,
+    #[arg(long, help = "Mongo database name")]
+    pub database: Option<String>,
+    #[arg(long, value_name = "PATH", help = "SQLite database file")]
+    pub sqlite_path: Option<String>,
+    #[arg(long, value_name = "FILE", help = "PEM certificate chain to serve HTTPS with; reloaded on SIGHUP")]
+    pub tls_cert: Option<String>,
+    #[arg(long, value_name = "FILE", help = "PEM private key of the certificate")]
+    pub tls_key: Option<String>,
+    #[arg(long, help = "Port to serve HTTPS on")]
+    pub tls_port: Option<u16>,
+    #[arg(long, value_enum, help = "What the HTTP port does while HTTPS is served")]
+    pub http: Option<HttpMode>,
+    #[arg(long = "cors-origin", value_name = "ORIGIN", help = "Origin allowed to read and submit scores, or * for any; repeatable")]
+    pub cors_origins: Vec<String>,
+    #[arg(long, help = "Key for the admin API; prefer the config file or ADMIN_API_KEY")]
+    pub admin_api_key: Option<String>,
+    #[arg(long, value_name = "KEYS", help = "Comma separated key_id:secret pairs scores are signed with")]
+    pub score_keys: Option<String>,
+    #[arg(long, help = "Board served at the routes without /games/{game}")]
+    pub default_game: Option<String>,
+    #[arg(long, value_delimiter = ',', help = "Additional boards to host, comma separated")]
+    pub games: Vec<String>,
+    #[arg(long, help = "Print the effective configuration, with secrets redacted, and exit")]
+    pub print_config: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    #[command(about = "Convert datetimes stored as strings by old versions and exit")]
+    MigrateDatetimes,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Mongo,
+    Sqlite,
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Backend, String> {
+        <Backend as ValueEnum>::from_str(value, true)
+    }
+}
+
+// What the plain HTTP port does once HTTPS is served.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpMode {
+    Redirect,
+    Serve,
+    Off,
+}
+
+impl FromStr for HttpMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<HttpMode, String> {
+        <HttpMode as ValueEnum>::from_str(value, true)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub default_game: String,
+    // key_id:secret pairs for boards without keys of their own.
+    pub score_keys: Option<String>,
+    // Calendar windows of boards without a time zone of their own start at
+    // midnight in this one.
+    pub time_zone: Option<String>,
+    pub admin_api_key: Option<String>,
+    pub storage: StorageConfig,
+    pub tls: TlsConfig,
+    pub cors: CorsConfig,
+    pub sessions: SessionConfig,
+    pub rate_limits: RateLimitConfig,
+    pub names: NameConfig,
+    pub games: BTreeMap<String, BoardSettings>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: String::from("0.0.0.0"),
+            port: 3000,
+            default_game: String::from("gurtle"),
+            score_keys: None,
+            time_zone: None,
+            admin_api_key: None,
+            storage: StorageConfig::default(),
+            tls: TlsConfig::default(),
+            cors: CorsConfig::default(),
+            sessions: SessionConfig::default(),
+            rate_limits: RateLimitConfig::default(),
+            names: NameConfig::default(),
+            games: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: Backend,
+    pub mongo_uri: String,
+    pub database: String,
+    pub sqlite_path: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            backend: Backend::Mongo,
+            mongo_uri: String::from("mongodb://localhost:27017"),
+            database: String::from("gurtle"),
+            sqlite_path: String::from("gurtle.db"),
+        }
+    }
+}
+
+// HTTPS is served on `port` when a certificate and key are given, both PEM
+// files; the certificate file holds the whole chain. Plain HTTP stays on the
+// main port as `http` says.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    pub port: u16,
+    pub http: HttpMode,
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig { cert_path: None, key_path: None, port: 3443, http: HttpMode::Redirect }
+    }
+}
+
+// Which websites may call the public API: reading boards, and starting
+// sessions, registering and submitting scores. By default any website may
+// read, and none may submit. The admin API can't be called cross-origin.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub reads: CorsScope,
+    pub submissions: CorsScope,
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            reads: CorsScope {
+                origins: vec![String::from("*")],
+                methods: vec![String::from("GET")],
+                headers: vec![String::from("Authorization")],
+                max_age: default_max_age(),
+            },
+            submissions: CorsScope {
+                origins: Vec::new(),
+                methods: vec![String::from("POST")],
+                headers: vec![String::from("Authorization"), String::from("Content-Type")],
+                max_age: default_max_age(),
+            },
+        }
+    }
+}
+
+// An origin of "*" allows any website. A scope given in the config file
+// lists its methods and headers itself. `max_age` is how many seconds
+// browsers may cache the answer to a preflight request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CorsScope {
+    #[serde(default)]
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+}
+
+fn default_max_age() -> u64 {
+    3600
+}
+
+// Durations in seconds. Outstanding session tokens are only kept in memory,
+// so they don't survive a restart and have to be submitted to the instance
+// that issued them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub max_age: i64,
+    pub min_length: i64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig { max_age: 7200, min_length: 5 }
+    }
+}
+
+// Limits are "requests/seconds".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub reads: String,
+    pub sessions: String,
+    pub submissions: String,
+    pub registrations: String,
+    pub admin: String,
+    pub names: String,
+    pub trusted_proxy_header: Option<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            reads: String::from("60/60"),
+            sessions: String::from("20/60"),
+            submissions: String::from("20/60"),
+            registrations: String::from("10/3600"),
+            admin: String::from("30/60"),
+            names: String::from("30/3600"),
+            trusted_proxy_header: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NameConfig {
+    pub min_length: usize,
+    pub max_length: usize,
+    // A file of blocked words, one per line; `*word*` blocks it anywhere in
+    // a name, not just as a whole word.
+    pub blocklist: Option<String>,
+}
+
+impl Default for NameConfig {
+    fn default() -> NameConfig {
+        NameConfig { min_length: 1, max_length: 24, blocklist: None }
+    }
+}
+
+// The settings of one board, `[games.<name>]` in the config file. Keys and
+// time zone fall back to the global ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoardSettings {
+    pub sort: String,
+    pub keys: Option<String>,
+    pub max_page_size: u64,
+    pub time_zone: Option<String>,
+    pub best_per_player: bool,
+    pub allow_anonymous: bool,
+    pub min_score: i32,
+    pub max_score: Option<i32>,
+    pub max_score_rate: Option<f64>,
+    pub suspicious_score: Option<i32>,
+    pub suspicious_score_rate: Option<f64>,
+    pub max_submissions_per_hour: Option<u32>,
+    pub replay_verifier: Option<String>,
+    pub replay_verifier_timeout: u64,
+    pub require_replay: bool,
+    pub max_replay_size: usize,
+}
+
+impl Default for BoardSettings {
+    fn default() -> BoardSettings {
+        BoardSettings {
+            sort: String::from("desc"),
+            keys: None,
+            max_page_size: 100,
+            time_zone: None,
+            best_per_player: false,
+            allow_anonymous: true,
+            min_score: 0,
+            max_score: None,
+            max_score_rate: None,
+            suspicious_score: None,
+            suspicious_score_rate: None,
+            max_submissions_per_hour: None,
+            replay_verifier: None,
+            replay_verifier_timeout: 10,
+            require_replay: false,
+            max_replay_size: 1024 * 1024,
+        }
+    }
+}
+
+// Values aren't repeated in errors, as they may be secrets.
+fn set<T: FromStr>(field: &mut T, key: &str) -> Result<(), String> {
+    if let Ok(value) = env::var(key) {
+        *field = value.parse().map_err(|_| format!("{} has an invalid value", key))?;
+    }
+    Ok(())
+}
+
+fn set_option<T: FromStr>(field: &mut Option<T>, key: &str) -> Result<(), String> {
+    if let Ok(value) = env::var(key) {
+        *field = Some(value.parse().map_err(|_| format!("{} has an invalid value", key))?);
+    }
+    Ok(())
+}
+
+fn list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+const REDACTED: &str = "<redacted>";
+
+fn redact(secret: &mut Option<String>) {
+    if secret.is_some() {
+        *secret = Some(String::from(REDACTED));
+    }
+}
+
+impl Config {
+
+    pub fn load(cli: &Cli) -> Result<Config, String> {
+        let mut config = match &cli.config {
+            Some(path) => Config::read(path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.apply_cli(cli);
+        config.games.entry(config.default_game.clone()).or_default();
+        for (name, board) in config.games.iter_mut() {
+            board.apply_env(name)?;
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+        toml::from_str(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // GAMES adds boards to those of the config file.
+    fn apply_env(&mut self) -> Result<(), String> {
+        set(&mut self.port, "PORT")?;
+        set(&mut self.default_game, "DEFAULT_GAME")?;
+        set_option(&mut self.score_keys, "SCORE_KEYS")?;
+        set_option(&mut self.time_zone, "TIME_ZONE")?;
+        set_option(&mut self.admin_api_key, "ADMIN_API_KEY")?;
+        set(&mut self.storage.backend, "STORAGE")?;
+        set(&mut self.storage.mongo_uri, "MONGO_URI")?;
+        set(&mut self.storage.database, "DATABASE")?;
+        set(&mut self.storage.sqlite_path, "SQLITE_PATH")?;
+        set_option(&mut self.tls.cert_path, "TLS_CERT_PATH")?;
+        set_option(&mut self.tls.key_path, "TLS_KEY_PATH")?;
+        set(&mut self.tls.port, "TLS_PORT")?;
+        set(&mut self.tls.http, "TLS_HTTP")?;
+        if let Ok(origins) = env::var("CORS_ORIGINS") {
+            self.cors.reads.origins = list(&origins);
+            self.cors.submissions.origins = list(&origins);
+        }
+        if let Ok(origins) = env::var("CORS_READ_ORIGINS") {
+            self.cors.reads.origins = list(&origins);
+        }
+        if let Ok(origins) = env::var("CORS_SUBMISSION_ORIGINS") {
+            self.cors.submissions.origins = list(&origins);
+        }
+        set(&mut self.sessions.max_age, "SESSION_MAX_AGE")?;
+        set(&mut self.sessions.min_length, "SESSION_MIN_LENGTH")?;
+        set(&mut self.rate_limits.reads, "RATE_LIMIT_READS")?;
+        set(&mut self.rate_limits.sessions, "RATE_LIMIT_SESSIONS")?;
+        set(&mut self.rate_limits.submissions, "RATE_LIMIT_SUBMISSIONS")?;
+        set(&mut self.rate_limits.registrations, "RATE_LIMIT_REGISTRATIONS")?;
+        set(&mut self.rate_limits.admin, "RATE_LIMIT_ADMIN")?;
+        set(&mut self.rate_limits.names, "RATE_LIMIT_NAMES")?;
+        set_option(&mut self.rate_limits.trusted_proxy_header, "TRUSTED_PROXY_HEADER")?;
+        set(&mut self.names.min_length, "NAME_MIN_LENGTH")?;
+        set(&mut self.names.max_length, "NAME_MAX_LENGTH")?;
+        set_option(&mut self.names.blocklist, "NAME_BLOCKLIST")?;
+        for name in list(&env::var("GAMES").unwrap_or_default()) {
+            self.games.entry(name).or_default();
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(bind) = &cli.bind {
+            self.bind = bind.clone();
+        }
+        if let Some(port) = cli.port {
+            self.port = port;
+        }
+        if let Some(backend) = cli.storage {
+            self.storage.backend = backend;
+        }
+        if let Some(uri) = &cli.mongo_uri {
+            self.storage.mongo_uri = uri.clone();
+        }
+        if let Some(database) = &cli.database {
+            self.storage.database = database.clone();
+        }
+        if let Some(path) = &cli.sqlite_path {
+            self.storage.sqlite_path = path.clone();
+        }
+        self.tls.cert_path = cli.tls_cert.clone().or(self.tls.cert_path.take());
+        self.tls.key_path = cli.tls_key.clone().or(self.tls.key_path.take());
+        if let Some(port) = cli.tls_port {
+            self.tls.port = port;
+        }
+        if let Some(http) = cli.http {
+            self.tls.http = http;
+        }
+        if !cli.cors_origins.is_empty() {
+            self.cors.reads.origins = cli.cors_origins.clone();
+            self.cors.submissions.origins = cli.cors_origins.clone();
+        }
+        self.admin_api_key = cli.admin_api_key.clone().or(self.admin_api_key.take());
+        self.score_keys = cli.score_keys.clone().or(self.score_keys.take());
+        if let Some(default) = &cli.default_game {
+            self.default_game = default.clone();
+        }
+        for name in &cli.games {
+            self.games.entry(name.clone()).or_default();
+        }
+    }
+
+    // The config as TOML, without secrets and the credentials of the Mongo
+    // URI.
+    pub fn redacted(&self) -> String {
+        let mut config = self.clone();
+        redact(&mut config.score_keys);
+        redact(&mut config.admin_api_key);
+        for board in config.games.values_mut() {
+            redact(&mut board.keys);
+        }
+        if let Some((scheme, rest)) = config.storage.mongo_uri.split_once("://") {
+            if let Some((_, host)) = rest.rsplit_once('@') {
+                config.storage.mongo_uri = format!("{}://{}@{}", scheme, REDACTED, host);
+            }
+        }
+        toml::to_string_pretty(&config).expect("The config can be written as TOML")
+    }
+
+}
+
+impl BoardSettings {
+
+    // Overrides settings with the board's `GAME_<NAME>_*` variables, e.g.
+    // GAME_GURTLE_SORT=asc.
+    fn apply_env(&mut self, name: &str) -> Result<(), String> {
+        let prefix = format!("GAME_{}_", name.to_uppercase().replace('-', "_"));
+        let key = |key: &str| format!("{}{}", prefix, key);
+        set(&mut self.sort, &key("SORT"))?;
+        set_option(&mut self.keys, &key("KEYS"))?;
+        set(&mut self.max_page_size, &key("MAX_PAGE_SIZE"))?;
+        set_option(&mut self.time_zone, &key("TIME_ZONE"))?;
+        set(&mut self.best_per_player, &key("BEST_PER_PLAYER"))?;
+        set(&mut self.allow_anonymous, &key("ALLOW_ANONYMOUS"))?;
+        set(&mut self.min_score, &key("MIN_SCORE"))?;
+        set_option(&mut self.max_score, &key("MAX_SCORE"))?;
+        set_option(&mut self.max_score_rate, &key("MAX_SCORE_RATE"))?;
+        set_option(&mut self.suspicious_score, &key("SUSPICIOUS_SCORE"))?;
+        set_option(&mut self.suspicious_score_rate, &key("SUSPICIOUS_SCORE_RATE"))?;
+        set_option(&mut self.max_submissions_per_hour, &key("MAX_SUBMISSIONS_PER_HOUR"))?;
+        set_option(&mut self.replay_verifier, &key("REPLAY_VERIFIER"))?;
+        set(&mut self.replay_verifier_timeout, &key("REPLAY_VERIFIER_TIMEOUT"))?;
+        set(&mut self.require_replay, &key("REQUIRE_REPLAY"))?;
+        set(&mut self.max_replay_size, &key("MAX_REPLAY_SIZE"))?;
+        Ok(())
+    }
+
+}
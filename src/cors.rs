@@ -0,0 +1,225 @@
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{
+            HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+        },
+        Method,
+    },
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+
+use crate::config::CorsScope;
+use crate::errors::ApiError;
+
+// Which policy a request falls under. The admin API has none: it is never
+// called from browsers, so cross-origin requests to it are refused outright.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Scope {
+    Reads,
+    Submissions,
+    Admin,
+}
+
+// The scope of a request by its path, which has to be the percent-decoded
+// path routes are matched against, not the raw one. Public routes are served
+// both at the root and under /games/{game}; this has to follow the routes in
+// main.rs and players.rs.
+fn scope(path: &str) -> Scope {
+    let path = match path.strip_prefix("/games/") {
+        Some(rest) => rest.find('/').map_or("", |slash| &rest[slash..]),
+        None => path,
+    };
+    match path.trim_start_matches('/').split('/').next() {
+        Some("admin") => Scope::Admin,
+        Some("session" | "submitscore" | "players") => Scope::Submissions,
+        _ => Scope::Reads,
+    }
+}
+
+// The origins, methods and request headers websites may use in one scope.
+// An origin of "*" allows any website.
+pub struct CorsPolicy {
+    any_origin: bool,
+    origins: Vec<HeaderValue>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    max_age: u64,
+}
+
+impl CorsPolicy {
+
+    // `name` is where the settings come from, for error messages.
+    pub fn new(name: &str, config: &CorsScope) -> Result<CorsPolicy, String> {
+        let mut policy = CorsPolicy { any_origin: false, origins: Vec::new(), methods: Vec::new(), headers: Vec::new(), max_age: config.max_age };
+        for origin in &config.origins {
+            if origin == "*" {
+                policy.any_origin = true;
+                continue;
+            }
+            let origin = origin.trim_end_matches('/');
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                return Err(format!("{}.origins: \"{}\" should be \"*\" or start with http:// or https://", name, origin));
+            }
+            policy.origins.push(HeaderValue::from_str(origin).map_err(|_| format!("{}.origins: \"{}\" is not a valid origin", name, origin))?);
+        }
+        for method in &config.methods {
+            policy.methods.push(method.to_uppercase().parse().map_err(|_| format!("{}.methods: \"{}\" is not a valid method", name, method))?);
+        }
+        for header in &config.headers {
+            policy.headers.push(header.parse().map_err(|_| format!("{}.headers: \"{}\" is not a valid header name", name, header))?);
+        }
+        Ok(policy)
+    }
+
+    fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        self.any_origin || self.origins.contains(origin)
+    }
+
+    fn allow_origin(&self, origin: &HeaderValue) -> HeaderValue {
+        match self.any_origin {
+            true => HeaderValue::from_static("*"),
+            false => origin.clone(),
+        }
+    }
+
+    fn allows_method(&self, method: &HeaderValue) -> bool {
+        method.to_str().ok()
+            .and_then(|method| method.parse::<Method>().ok())
+            .is_some_and(|method| self.methods.contains(&method))
+    }
+
+    // Header names are case insensitive and parse to lower case.
+    fn allows_headers(&self, headers: Option<&HeaderValue>) -> bool {
+        let Some(headers) = headers else {
+            return true;
+        };
+        headers.to_str().is_ok_and(|headers| {
+            headers.split(',')
+                .map(str::trim)
+                .filter(|header| !header.is_empty())
+                .all(|header| header.parse::<HeaderName>().is_ok_and(|header| self.headers.contains(&header)))
+        })
+    }
+
+    fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+        let joined = items.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(", ");
+        HeaderValue::from_str(&joined).expect("Methods and header names are valid header values")
+    }
+
+    fn preflight(&self, origin: &HeaderValue) -> HttpResponse {
+        HttpResponse::NoContent()
+            .insert_header((ACCESS_CONTROL_ALLOW_ORIGIN, self.allow_origin(origin)))
+            .insert_header((ACCESS_CONTROL_ALLOW_METHODS, CorsPolicy::join(&self.methods)))
+            .insert_header((ACCESS_CONTROL_ALLOW_HEADERS, CorsPolicy::join(&self.headers)))
+            .insert_header((ACCESS_CONTROL_MAX_AGE, self.max_age))
+            .insert_header((VARY, "Origin"))
+            .finish()
+    }
+
+}
+
+pub struct CorsPolicies {
+    pub reads: CorsPolicy,
+    pub submissions: CorsPolicy,
+}
+
+fn forbidden(message: &str) -> Error {
+    ApiError::forbidden("cors_forbidden", message).into()
+}
+
+// Answers preflight requests and adds CORS headers to the responses of
+// allowed cross-origin requests. Cross-origin requests from origins that
+// aren't allowed are refused rather than left for the browser to block, as
+// simple requests would otherwise still be carried out.
+pub async fn handle(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(origin) = req.headers().get(ORIGIN).cloned() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let policies = req.app_data::<web::Data<CorsPolicies>>().expect("CORS policies are set up").clone();
+    let policy = match scope(req.match_info().as_str()) {
+        Scope::Reads => &policies.reads,
+        Scope::Submissions => &policies.submissions,
+        Scope::Admin => return Err(forbidden("Admin API can't be called cross-origin")),
+    };
+    if !policy.allows_origin(&origin) {
+        return Err(forbidden("Origin is not allowed"));
+    }
+    if req.method() == Method::OPTIONS {
+        if let Some(method) = req.headers().get(ACCESS_CONTROL_REQUEST_METHOD) {
+            if !policy.allows_method(method) {
+                return Err(forbidden("Method is not allowed"));
+            }
+            if !policy.allows_headers(req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS)) {
+                return Err(forbidden("Request headers are not allowed"));
+            }
+            return Ok(req.into_response(policy.preflight(&origin)));
+        }
+    }
+    let mut response = next.call(req).await?.map_into_boxed_body();
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, policy.allow_origin(&origin));
+    headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static("retry-after, x-total-count"));
+    headers.append(VARY, HeaderValue::from_static("Origin"));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_follow_the_routes() {
+        assert_eq!(scope("/scores/alltime"), Scope::Reads);
+        assert_eq!(scope("/games/pong/around/daily"), Scope::Reads);
+        assert_eq!(scope("/metrics"), Scope::Reads);
+        assert_eq!(scope("/submitscore"), Scope::Submissions);
+        assert_eq!(scope("/games/pong/session/start"), Scope::Submissions);
+        assert_eq!(scope("/players/register"), Scope::Submissions);
+        assert_eq!(scope("/admin/bans"), Scope::Admin);
+        assert_eq!(scope("/admin/games/pong/scores"), Scope::Admin);
+        assert_eq!(scope("//admin/bans"), Scope::Admin);
+    }
+
+    #[actix_web::test]
+    async fn scopes_are_picked_from_the_decoded_path() {
+        use actix_web::{test, App};
+        let any = |methods: &[&str]| CorsScope {
+            origins: vec![String::from("*")],
+            methods: methods.iter().map(|method| method.to_string()).collect(),
+            headers: Vec::new(),
+            max_age: 60,
+        };
+        let policies = CorsPolicies {
+            reads: CorsPolicy::new("reads", &any(&["GET"])).unwrap(),
+            submissions: CorsPolicy::new("submissions", &CorsScope { origins: Vec::new(), ..any(&["POST"]) }).unwrap(),
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(policies))
+                .wrap(actix_web::middleware::from_fn(handle))
+                .route("/submitscore", web::post().to(HttpResponse::Ok))
+                .route("/admin/bans", web::get().to(HttpResponse::Ok))
+                .route("/scores", web::get().to(HttpResponse::Ok)),
+        ).await;
+        let request = |method: Method, path: &str| test::TestRequest::default()
+            .method(method)
+            .uri(path)
+            .insert_header((ORIGIN, "https://evil.example"))
+            .to_request();
+        let status = |request| async {
+            match test::try_call_service(&app, request).await {
+                Ok(response) => response.status(),
+                Err(err) => err.as_response_error().status_code(),
+            }
+        };
+        assert_eq!(status(request(Method::POST, "/%73ubmitscore")).await, 403);
+        assert_eq!(status(request(Method::GET, "/%61dmin/bans")).await, 403);
+        assert_eq!(status(request(Method::GET, "/%73cores")).await, 200);
+    }
+
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+use std::time::Duration;
+use serde::Serialize;
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpRequest, HttpResponse, ResponseError,
+};
+
+use crate::names::NameError;
+use crate::replays::ReplayError;
+use crate::rules::RuleViolation;
+use crate::session::SessionError;
+use crate::signing::SignatureError;
+use crate::store::StoreError;
+use crate::windows::WindowError;
+
+// An error as it is answered: a JSON body with a stable `code` for clients to
+// act on and a `message` for people. Internal errors are logged and answered
+// without their details.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    retry_after: Option<Duration>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ApiError {
+
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError { status, code, message: message.into(), retry_after: None }
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn unauthorized(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::UNAUTHORIZED, code, message)
+    }
+
+    pub fn forbidden(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::FORBIDDEN, code, message)
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::CONFLICT, code, message)
+    }
+
+    pub fn rate_limited(retry_after: Duration) -> ApiError {
+        let mut err = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", "Rate limit exceeded");
+        err.retry_after = Some(retry_after);
+        err
+    }
+
+    pub fn internal(err: impl fmt::Display) -> ApiError {
+        eprintln!("Internal error: {}", err);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error")
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        if let Some(retry_after) = self.retry_after {
+            response.insert_header((RETRY_AFTER, retry_after.as_secs_f64().ceil().max(1.0) as u64));
+        }
+        response.json(ErrorBody { code: self.code, message: &self.message })
+    }
+
+}
+
+// Lets handlers take `Result<T, ApiError>` of extractors that fail with
+// actix errors, such as web::Json with `json_error` as its error handler.
+impl From<actix_web::Error> for ApiError {
+    fn from(err: actix_web::Error) -> ApiError {
+        match err.as_error::<ApiError>() {
+            Some(err) => err.clone(),
+            None => ApiError::new(err.as_response_error().status_code(), "invalid_request", err.to_string()),
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> ApiError {
+        ApiError::internal(err)
+    }
+}
+
+impl From<WindowError> for ApiError {
+    fn from(err: WindowError) -> ApiError {
+        ApiError::bad_request("invalid_window", err.to_string())
+    }
+}
+
+impl From<NameError> for ApiError {
+    fn from(err: NameError) -> ApiError {
+        ApiError::bad_request("invalid_name", err.to_string())
+    }
+}
+
+impl From<ReplayError> for ApiError {
+    fn from(err: ReplayError) -> ApiError {
+        ApiError::bad_request("invalid_replay", err.to_string())
+    }
+}
+
+impl From<SessionError> for ApiError {
+    fn from(err: SessionError) -> ApiError {
+        ApiError::forbidden("invalid_session", err.to_string())
+    }
+}
+
+impl From<SignatureError> for ApiError {
+    fn from(err: SignatureError) -> ApiError {
+        ApiError::forbidden("invalid_signature", err.to_string())
+    }
+}
+
+impl From<RuleViolation> for ApiError {
+    fn from(err: RuleViolation) -> ApiError {
+        ApiError::forbidden("rule_violation", err.to_string())
+    }
+}
+
+// Malformed bodies, query strings and paths are answered like all other
+// errors instead of with actix's plain text.
+pub fn json_error(err: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    let code = match err.status_code() {
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        _ => "invalid_request",
+    };
+    ApiError::new(err.status_code(), code, err.to_string()).into()
+}
+
+pub fn query_error(err: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    ApiError::bad_request("invalid_request", err.to_string()).into()
+}
+
+pub fn path_error(err: PathError, _: &HttpRequest) -> actix_web::Error {
+    ApiError::not_found("not_found", err.to_string()).into()
+}
+
+pub async fn no_route() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::not_found("not_found", "No such route"))
+}
@@ -1,24 +1,90 @@
-use std::env;
+use std::process;
+use std::sync::Arc;
+use clap::Parser;
 use serde::{Serialize, Deserialize};
-use mongodb::{bson::doc, Client, options::{ClientOptions, FindOptions}, Collection};
-use actix_web::{get, post, web, App, HttpResponse, HttpServer};
-use actix_cors::Cors;
-use futures::stream::TryStreamExt;
+use actix_web::{get, post, http::header::{ContentType, HeaderName, CONTENT_ENCODING, VARY}, middleware::{from_fn, Condition}, web, App, HttpRequest, HttpResponse, HttpServer};
 use chrono::{Duration, offset::Utc};
-use sha256;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Entry {
-    name: String,
-    score: i32,
-    datetime: String,
-}
+mod admin;
+mod audit;
+mod boards;
+mod config;
+mod cors;
+mod errors;
+mod metrics;
+mod names;
+mod players;
+mod ratelimit;
+mod replays;
+mod rules;
+mod session;
+mod signing;
+mod tls;
+mod store;
+mod windows;
+
+use admin::{admin_routes, AdminKey};
+use boards::{page_offset, valid_board_name, Board, BoardConfig, BoardQuery, Boards, CurrentBoard};
+use config::{Backend, Cli, Command, Config, CorsConfig, HttpMode, NameConfig, RateLimitConfig, SessionConfig, StorageConfig, TlsConfig};
+use cors::{CorsPolicies, CorsPolicy};
+use errors::ApiError;
+use metrics::metrics_routes;
+use names::{fold, normalize, NameFilter, NamePolicy, WordList};
+use players::{hash_token, player_routes, PlayerAuth};
+use ratelimit::{Limit, Limiter, RateLimit, RateLimits};
+use replays::VerifyError;
+use rules::Verdict;
+use session::Sessions;
+use signing::score_message;
+use tls::{CertResolver, HttpsPort};
+use store::{AuditKind, AuditStore, BanMode, BanStore, BanTarget, Entry, EntryStatus, PlayerStore, ScoreQuery, Storage, StoreError};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct SubmittedEntry {
     name: String,
     score: i32,
+    session: String,
+    key_id: String,
     hash: String,
+    // The base64 encoded input replay of the run, if the client recorded one.
+    replay: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScoresPath {
+    duration: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct PageQuery {
+    offset: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct RankedEntry {
+    rank: u64,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+#[derive(Deserialize, Debug)]
+struct AroundQuery {
+    score: Option<i32>,
+    name: Option<String>,
+    count: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Around {
+    position: u64,
+    entries: Vec<RankedEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PositionPath {
+    duration: String,
+    score: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -26,101 +92,789 @@ struct Position {
     position: u64,
 }
 
-async fn set_up_db(uri: &str) -> Result<Client, mongodb::error::Error> {
-    let client_options = ClientOptions::parse(uri).await?;
-    let client = Client::with_options(client_options)?;
-    Ok(client)
+// The state of a held submission, which the submitter can poll with the
+// review id it got back.
+#[derive(Serialize, Debug)]
+struct Review {
+    review_id: String,
+    status: EntryStatus,
 }
 
-#[get("/scores/{duration}")]
-async fn get_scores(path: web::Path<String>, collection: web::Data<Collection<Entry>>) -> HttpResponse {
-    let duration = path.into_inner();
-    let now = Utc::now();
-    let beginning = match duration.as_str() {
-        "weekly" => now - Duration::weeks(1),
-        "monthly" => now - Duration::weeks(4),
-        _ => now,
+async fn set_up_storage(config: &StorageConfig) -> Storage {
+    match config.backend {
+        Backend::Mongo => Storage::mongo(&config.mongo_uri, &config.database).await
+            .expect("Should be able to connect do Mongo DB"),
+        Backend::Sqlite => Storage::sqlite(&config.sqlite_path).expect("Should be able to open SQLite database"),
+        Backend::Memory => Storage::Memory,
+    }
+}
+
+// Checks the settings of all boards before any store is opened.
+fn board_configs(config: &Config) -> Result<Vec<(String, BoardConfig)>, String> {
+    config.games.iter()
+        .map(|(name, settings)| {
+            if !valid_board_name(name) {
+                return Err(format!("Game name \"{}\" may only contain letters, digits, _ and -", name));
+            }
+            Ok((name.clone(), BoardConfig::new(name, settings, config)?))
+        })
+        .collect()
+}
+
+// The default board keeps the original `scores` collection and is also
+// served at the routes without /games/{game}.
+async fn set_up_boards(default: &str, configs: Vec<(String, BoardConfig)>, storage: &Storage) -> Boards {
+    let mut boards = Boards::new(default);
+    for (name, config) in configs {
+        let table = match name == default {
+            true => String::from("scores"),
+            false => format!("scores_{}", name),
+        };
+        let store = storage.scores(table.as_str(), config.sort).await
+            .expect("Should be able to open the score store");
+        boards.insert(Board { name, config, store });
+    }
+    boards
+}
+
+fn set_up_rate_limits(config: &RateLimitConfig) -> Result<RateLimits, String> {
+    let limit = |key: &str, value: &str| value.parse::<Limit>().map_err(|err| format!("rate_limits.{}: {}", key, err));
+    Ok(RateLimits {
+        reads: Limiter::new(limit("reads", &config.reads)?),
+        sessions: Limiter::new(limit("sessions", &config.sessions)?),
+        submissions: Limiter::new(limit("submissions", &config.submissions)?),
+        registrations: Limiter::new(limit("registrations", &config.registrations)?),
+        admin: Limiter::new(limit("admin", &config.admin)?),
+        names: Limiter::new(limit("names", &config.names)?),
+        proxy_header: config.trusted_proxy_header.clone(),
+    })
+}
+
+fn set_up_name_policy(config: &NameConfig) -> Result<NamePolicy, String> {
+    if config.min_length > config.max_length {
+        return Err(String::from("names.min_length should not be larger than names.max_length"));
+    }
+    let filter = match &config.blocklist {
+        Some(path) => Some(Box::new(WordList::load(path).map_err(|err| format!("names.blocklist: {}", err))?) as Box<dyn NameFilter>),
+        None => None,
     };
-    let mut scores: Vec<Entry> = Vec::new();
-    let filter = match duration.as_str() {
-        "alltime" => doc! {},
-        _ => doc! {
-            "datetime": { "$gte": beginning.to_string() }
+    Ok(NamePolicy { min_length: config.min_length, max_length: config.max_length, filter })
+}
+
+fn set_up_sessions(config: &SessionConfig) -> Result<Sessions, String> {
+    if config.max_age <= 0 || config.min_length < 0 || config.min_length > config.max_age {
+        return Err(String::from("sessions.min_length should be between 0 and sessions.max_age, which should be positive"));
+    }
+    Ok(Sessions::new(Duration::seconds(config.max_age), Duration::seconds(config.min_length)))
+}
+
+fn set_up_cors(config: &CorsConfig) -> Result<CorsPolicies, String> {
+    Ok(CorsPolicies {
+        reads: CorsPolicy::new("cors.reads", &config.reads)?,
+        submissions: CorsPolicy::new("cors.submissions", &config.submissions)?,
+    })
+}
+
+fn set_up_tls(config: &TlsConfig) -> Result<Option<Arc<CertResolver>>, String> {
+    match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) => Ok(Some(Arc::new(CertResolver::new(cert_path, key_path)?))),
+        (None, None) => Ok(None),
+        _ => Err(String::from("tls.cert_path and tls.key_path should be set together")),
+    }
+}
+
+// Configuration errors stop the server before anything is started.
+fn invalid_config(err: String) -> ! {
+    eprintln!("Invalid configuration: {}", err);
+    process::exit(2)
+}
+
+// Ranks are shared by equal scores, so an entry's rank is the number of
+// strictly better entries plus one, the same number get_position reports.
+async fn rank_entries(board: &Board, query: &ScoreQuery, entries: Vec<Entry>, offset: u64) -> Result<Vec<RankedEntry>, StoreError> {
+    let mut ranked: Vec<RankedEntry> = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.into_iter().enumerate() {
+        let rank = match ranked.last() {
+            Some(previous) if previous.entry.score == entry.score => previous.rank,
+            Some(_) => offset.saturating_add(index as u64 + 1),
+            None => board.store.count_better(query, entry.score).await?.saturating_add(1),
+        };
+        ranked.push(RankedEntry { rank, entry });
+    }
+    Ok(ranked)
+}
+
+// The total number of entries in the window is sent in X-Total-Count, so the
+// body stays the plain list of entries older clients expect.
+#[get("/scores/{duration}", wrap = "RateLimit::reads()")]
+async fn get_scores(req: HttpRequest, path: web::Path<ScoresPath>, page: web::Query<PageQuery>, query: web::Query<BoardQuery>, board: CurrentBoard, auth: PlayerAuth) -> Result<HttpResponse, ApiError> {
+    let query = board.query(&path.duration, &query, auth.viewer(&req))?;
+    let offset = page_offset(page.offset)?;
+    let limit = page.limit.unwrap_or(10).min(board.config.max_page_size);
+    let scores = board.store.top(&query, offset, limit).await?;
+    let total = board.store.count(&query).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((HeaderName::from_static("x-total-count"), total))
+        .json(rank_entries(&board, &query, scores, offset).await?))
+}
+
+#[get("/position/{duration}/{score}", wrap = "RateLimit::reads()")]
+async fn get_position(req: HttpRequest, path: web::Path<PositionPath>, query: web::Query<BoardQuery>, board: CurrentBoard, auth: PlayerAuth) -> Result<HttpResponse, ApiError> {
+    let query = board.query(&path.duration, &query, auth.viewer(&req))?;
+    let position = board.store.count_better(&query, path.score).await? + 1;
+    Ok(HttpResponse::Ok().json(Position { position }))
+}
+
+// Returns the entries ranked around a score, or around the best score of a
+// name, as one slice of the get_scores ordering: `count` entries above it and
+// `count` at or below it. Equal scores are ordered newest first, so the slice
+// around a name starts from that entry's own place among its ties and always
+// contains it.
+#[get("/around/{duration}", wrap = "RateLimit::reads()")]
+async fn get_around(req: HttpRequest, path: web::Path<ScoresPath>, around: web::Query<AroundQuery>, query: web::Query<BoardQuery>, board: CurrentBoard, auth: PlayerAuth) -> Result<HttpResponse, ApiError> {
+    let query = board.query(&path.duration, &query, auth.viewer(&req))?;
+    let count = around.count.unwrap_or(5).min(board.config.max_page_size);
+    let (score, best) = match (&around.name, around.score) {
+        (Some(name), _) => match board.store.best_of(&query, &normalize(name)).await? {
+            Some(best) => (best.score, Some(best)),
+            None => return Err(ApiError::not_found("no_score", "No score for this name")),
         },
+        (None, Some(score)) => (score, None),
+        (None, None) => return Err(ApiError::bad_request("invalid_request", "Either score or name is required")),
     };
-    let options = FindOptions::builder()
-        .sort(doc! {"score": -1, "datetime": -1})
-        .limit(10)
-        .build();
-    let mut cursor = collection.into_inner()
-        .find(filter, options)
-        .await.unwrap();
-    while let Some(score) = cursor.try_next().await.unwrap() {
-        scores.push(score);
-    }
-    HttpResponse::Ok().json(scores)
-}
-
-#[get("/position/{duration}/{score}")]
-async fn get_position(path: web::Path<(String, i32)>, collection: web::Data<Collection<Entry>>) -> HttpResponse {
-    let (duration, score) = path.into_inner();
-    let now = Utc::now();
-    let beginning = match duration.as_str() {
-        "weekly" => now - Duration::weeks(1),
-        "monthly" => now - Duration::weeks(4),
-        _ => now,
+    let better = board.store.count_better(&query, score).await?;
+    let (ahead, below) = match &best {
+        Some(best) => (board.store.count_ahead(&query, best).await?, count.max(1)),
+        None => (better, count),
     };
-    let filter = match duration.as_str() {
-        "alltime" => doc! { "score": {"$gt": score} },
-        _ => doc! {
-            "datetime": { "$gte": beginning.to_string() },
-            "score": {"$gt": score}
-        },
-    };
-    let position = collection.into_inner().count_documents(filter, None).await.unwrap() + 1;
-    HttpResponse::Ok().json(Position { position })
+    let offset = ahead.saturating_sub(count);
+    let entries = board.store.top(&query, offset, ahead - offset + below).await?;
+    Ok(HttpResponse::Ok().json(Around {
+        position: better + 1,
+        entries: rank_entries(&board, &query, entries, offset).await?,
+    }))
+}
+
+#[post("/session/start", wrap = "RateLimit::sessions()")]
+async fn start_session(board: CurrentBoard, sessions: web::Data<Sessions>) -> HttpResponse {
+    HttpResponse::Ok().json(sessions.start(&board.name, Utc::now()))
 }
 
-#[post("/submitscore")]
-async fn submit_score(collection: web::Data<Collection<Entry>>, submitted: web::Json<SubmittedEntry>) -> HttpResponse {
+// Scores of authenticated players are attributed to their id and have to use
+// their registered name. Anonymous scores can't use a registered name, and are
+// only accepted by boards that allow them. The hash covers the name as
+// submitted, the entry stores its normalized form. Scores of banned names,
+// players or IPs are rejected, those of shadow-banned ones stored as shadowed.
+// Scores breaking the board's rules are rejected, suspicious ones stored as
+// pending review. Replays go through the board's verifier: ones that don't
+// reproduce the score are rejected, ones it can't check held for review.
+// Every attempt is recorded in the audit log, rejections with the message
+// they are answered with. Those with an unknown token or an unreadable body
+// are recorded here too, and rate limited ones by the middleware.
+#[post("/submitscore", wrap = "RateLimit::submissions()")]
+#[allow(clippy::too_many_arguments)]
+async fn submit_score(req: HttpRequest, board: CurrentBoard, sessions: web::Data<Sessions>, limits: web::Data<RateLimits>, players: web::Data<dyn PlayerStore>, bans: web::Data<dyn BanStore>, audit: web::Data<dyn AuditStore>, policy: web::Data<NamePolicy>, auth: Result<PlayerAuth, ApiError>, submitted: Result<web::Json<SubmittedEntry>, ApiError>) -> Result<HttpResponse, ApiError> {
     let now = Utc::now();
-    let submitted = submitted.into_inner();
-    let value = sha256::digest(format!("{}TheTurtle{}", submitted.name, submitted.score));
-    if value != submitted.hash {
-        return HttpResponse::Forbidden().body("Score rejected: Invalid hash");
-    }
-    let data = Entry {
-        name: submitted.name,
-        score: submitted.score,
-        datetime: now.to_string(),
+    let mut event = audit::request_event(&req, AuditKind::Submission, "submit");
+    event.game = Some(board.name.clone());
+    if let Ok(submitted) = &submitted {
+        event.name = Some(audit::truncate(&submitted.name));
+        event.score = Some(submitted.score);
+    }
+    let (auth, submitted) = match (auth, submitted) {
+        (Ok(auth), Ok(submitted)) => (auth, submitted.into_inner()),
+        (Err(err), _) | (_, Err(err)) => {
+            event.reason = Some(err.message().to_string());
+            audit::record(audit.as_ref(), event).await;
+            metrics::record_submission(&board.name, err.code());
+            return Err(err);
+        },
     };
-    let result = collection.into_inner().insert_one(data, None).await;
-    match result {
-        Ok(_) => HttpResponse::Ok().body("Score added"),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    let message = score_message(&submitted.session, &submitted.name, submitted.score);
+    let name = policy.check(&submitted.name);
+    let outcome = async {
+        let name = name.clone()?;
+        let replay = match &submitted.replay {
+            Some(replay) => Some(replays::decode(replay, board.config.max_replay_size)?),
+            None if board.config.require_replay => return Err(ApiError::bad_request("replay_required", "Replay is required")),
+            None => None,
+        };
+        match &auth.0 {
+            Some(player) if player.name != name => {
+                return Err(ApiError::forbidden("name_mismatch", "Name does not match the player"));
+            },
+            Some(_) => {},
+            None if !board.config.allow_anonymous => {
+                return Err(ApiError::unauthorized("authentication_required", "Authentication required"));
+            },
+            None => if players.by_name_key(&fold(&name)).await?.is_some() {
+                return Err(ApiError::forbidden("name_registered", "Name is registered"));
+            },
+        }
+        let ip = limits.client_ip(&req);
+        let mut subjects = vec![(BanTarget::Name, fold(&name))];
+        if let Some(player) = &auth.0 {
+            subjects.push((BanTarget::Player, player.id.clone()));
+        }
+        if let Some(ip) = &ip {
+            subjects.push((BanTarget::Ip, ip.clone()));
+        }
+        let bans = bans.matching(&subjects).await?;
+        if bans.iter().any(|ban| ban.mode == BanMode::Ban) {
+            return Err(ApiError::forbidden("banned", "Banned"));
+        }
+        board.config.keyring.verify(&submitted.key_id, &message, &submitted.hash)?;
+        // Limited submissions can be retried, so they mustn't use up their
+        // session.
+        let player_id = auth.player_id();
+        let submitter = player_id.as_ref().unwrap_or(&name);
+        limits.names.check(&format!("{}/{}", board.name, submitter)).map_err(ApiError::rate_limited)?;
+        board.config.rules.check_submitter(submitter).map_err(ApiError::rate_limited)?;
+        let session_length = sessions.consume(&board.name, &submitted.session, now)?;
+        let verdict = board.config.rules.check(board.config.sort, submitted.score, session_length)?;
+        let mut held = match verdict {
+            Verdict::Publish => None,
+            Verdict::Hold(reason) => Some(reason),
+        };
+        if let (Some(verifier), Some(replay)) = (&board.config.verifier, &replay) {
+            match verifier.verify(&board.name, submitted.score, replay).await {
+                Ok(()) => {},
+                Err(err @ VerifyError::Mismatch(_)) => return Err(ApiError::forbidden("replay_mismatch", err.to_string())),
+                Err(err @ VerifyError::Failed(_)) => held = held.or(Some(err.to_string())),
+            }
+        }
+        let data = Entry {
+            id: None,
+            player_id,
+            name,
+            score: submitted.score,
+            datetime: now,
+            shadow: !bans.is_empty(),
+            status: match held {
+                Some(_) => EntryStatus::Pending,
+                None => EntryStatus::Published,
+            },
+            has_replay: false,
+            ip_hash: ip.as_deref().map(hash_token),
+        };
+        let id = board.store.insert(data).await?;
+        if let Some(replay) = replay {
+            board.store.save_replay(&id, replays::compress(&replay)).await?;
+        }
+        Ok((id, held))
+    }.await;
+    event.accepted = outcome.is_ok();
+    event.reason = match &outcome {
+        Ok((_, held)) => held.as_ref().map(|reason| format!("Held for review: {}", reason)),
+        Err(err) => Some(err.message().to_string()),
+    };
+    event.entry_id = outcome.as_ref().ok().map(|(id, _)| id.clone());
+    event.name = Some(name.unwrap_or_else(|_| audit::truncate(&submitted.name)));
+    event.player_id = auth.player_id();
+    event.score = Some(submitted.score);
+    audit::record(audit.as_ref(), event).await;
+    metrics::record_submission(&board.name, match &outcome {
+        Ok((_, Some(_))) => "held",
+        Ok((_, None)) => "accepted",
+        Err(err) => err.code(),
+    });
+    Ok(match outcome? {
+        (id, Some(_)) => HttpResponse::Accepted().json(Review { review_id: id, status: EntryStatus::Pending }),
+        (_, None) => HttpResponse::Ok().body("Score added"),
+    })
+}
+
+#[get("/reviews/{id}", wrap = "RateLimit::reads()")]
+async fn get_review(board: CurrentBoard, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    match board.store.get(&id).await? {
+        Some(entry) => Ok(HttpResponse::Ok().json(Review { review_id: id.into_inner(), status: entry.status })),
+        None => Err(ApiError::not_found("not_found", "No such review")),
     }
 }
 
+// The replay of a visible entry. Stored replays are gzip compressed, and sent
+// that way to clients accepting it.
+#[get("/replays/{id}", wrap = "RateLimit::reads()")]
+async fn get_replay(req: HttpRequest, board: CurrentBoard, auth: PlayerAuth, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let visible = ScoreQuery { viewer: auth.viewer(&req), ..ScoreQuery::default() };
+    if !board.store.get(&id).await?.is_some_and(|entry| visible.includes(&entry)) {
+        return Err(ApiError::not_found("not_found", "No such entry"));
+    }
+    let replay = board.store.replay(&id).await?
+        .ok_or_else(|| ApiError::not_found("no_replay", "Entry has no replay"))?;
+    let mut response = HttpResponse::Ok();
+    response.content_type(ContentType::octet_stream()).insert_header((VARY, "Accept-Encoding"));
+    if replays::accepts_gzip(&req) {
+        return Ok(response.insert_header((CONTENT_ENCODING, "gzip")).body(replay));
+    }
+    Ok(response.body(replays::decompress(&replay).map_err(ApiError::internal)?))
+}
+
+fn board_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_scores)
+        .service(get_position)
+        .service(get_around)
+        .service(start_session)
+        .service(submit_score)
+        .service(get_review)
+        .service(get_replay);
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
 
-    let uri = env::var("MONGO_URI").unwrap_or(String::from("mongodb://localhost:27017"));
-    let client = set_up_db(uri.as_str()).await.expect("Should be able to connect do Mongo DB");
-    let db = client.database(env::var("DATABASE").unwrap_or(String::from("gurtle")).as_str());
-    let collection = db.collection::<Entry>("scores");
-    let port: u16 = env::var("PORT").unwrap_or(String::from("3000")).parse().unwrap_or(3000);
-
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
-        App::new()
-            .wrap(cors)
-            .app_data(web::Data::new(collection.clone()))
-            .service(get_scores)
-            .service(get_position)
-            .service(submit_score)
-    })
-    .bind(("0.0.0.0", port))?
-    .run()
-    .await
+    let cli = Cli::parse();
+    let config = Config::load(&cli).unwrap_or_else(|err| invalid_config(err));
+    let board_configs = board_configs(&config).unwrap_or_else(|err| invalid_config(err));
+    let sessions = set_up_sessions(&config.sessions).unwrap_or_else(|err| invalid_config(err));
+    let limits = set_up_rate_limits(&config.rate_limits).unwrap_or_else(|err| invalid_config(err));
+    let name_policy = set_up_name_policy(&config.names).unwrap_or_else(|err| invalid_config(err));
+    let cors = set_up_cors(&config.cors).unwrap_or_else(|err| invalid_config(err));
+    let tls = set_up_tls(&config.tls).unwrap_or_else(|err| invalid_config(err));
+    if cli.print_config {
+        print!("{}", config.redacted());
+        return Ok(());
+    }
 
-}
\ No newline at end of file
+    let storage = set_up_storage(&config.storage).await;
+    let boards = set_up_boards(&config.default_game, board_configs, &storage).await;
+    if cli.command == Some(Command::MigrateDatetimes) {
+        for board in boards.iter() {
+            let migrated = board.store.migrate_datetimes().await.expect("Should be able to migrate datetimes");
+            println!("Migrated {} entries of {}", migrated, board.name);
+        }
+        return Ok(());
+    }
+    // Submissions carry replays base64 encoded, so the body may be a third
+    // larger than the largest replay allowed.
+    let max_replay_size = boards.iter().map(|board| board.config.max_replay_size).max().unwrap_or(0);
+    let json = web::JsonConfig::default()
+        .limit(max_replay_size / 3 * 4 + 64 * 1024)
+        .error_handler(errors::json_error);
+    let boards = web::Data::new(boards);
+    let players: web::Data<dyn PlayerStore> = web::Data::from(storage.players().await.expect("Should be able to open the player store"));
+    let bans: web::Data<dyn BanStore> = web::Data::from(storage.bans().await.expect("Should be able to open the ban store"));
+    let audit: web::Data<dyn AuditStore> = web::Data::from(storage.audit().await.expect("Should be able to open the audit log"));
+    let sessions = web::Data::new(sessions);
+    let limits = web::Data::new(limits);
+    let name_policy = web::Data::new(name_policy);
+    let admin_key = config.admin_api_key.as_deref().map(|key| web::Data::new(AdminKey::new(key)));
+    let cors = web::Data::new(cors);
+    let redirect = tls.is_some() && config.tls.http == HttpMode::Redirect;
+    let https_port = web::Data::new(HttpsPort(config.tls.port));
+
+    let server = HttpServer::new(move || {
+        let mut app = App::new();
+        if let Some(admin_key) = &admin_key {
+            app = app.app_data(admin_key.clone());
+        }
+        app
+            .wrap(from_fn(cors::handle))
+            .wrap(Condition::new(redirect, from_fn(tls::redirect_to_https)))
+            .wrap(from_fn(metrics::track))
+            .app_data(json.clone())
+            .app_data(web::QueryConfig::default().error_handler(errors::query_error))
+            .app_data(web::PathConfig::default().error_handler(errors::path_error))
+            .app_data(cors.clone())
+            .app_data(https_port.clone())
+            .app_data(boards.clone())
+            .app_data(sessions.clone())
+            .app_data(limits.clone())
+            .app_data(players.clone())
+            .app_data(bans.clone())
+            .app_data(audit.clone())
+            .app_data(name_policy.clone())
+            .configure(admin_routes)
+            .configure(player_routes)
+            .configure(metrics_routes)
+            .configure(board_routes)
+            .service(web::scope("/games/{game}").configure(board_routes))
+            .default_service(web::to(errors::no_route))
+    });
+    let server = match tls {
+        Some(resolver) => {
+            #[cfg(unix)]
+            actix_web::rt::spawn(tls::reload_on_hangup(resolver.clone()));
+            let server = server.bind_rustls_0_23((config.bind.as_str(), config.tls.port), resolver.server_config())?;
+            match config.tls.http {
+                HttpMode::Off => server,
+                _ => server.bind((config.bind.as_str(), config.port))?,
+            }
+        },
+        None => server.bind((config.bind.as_str(), config.port))?,
+    };
+    server.run().await
+
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::MessageBody,
+        dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+        test,
+    };
+    use std::collections::HashMap;
+    use base64::Engine;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use config::BoardSettings;
+    use store::{parse_legacy_datetime, SortOrder};
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    struct Ranked {
+        rank: u64,
+        name: String,
+        datetime: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct AroundBody {
+        position: u64,
+        entries: Vec<Ranked>,
+    }
+
+    // A leader, seven names tied at 100 from alice, the oldest, to golf, the
+    // newest, and one name below them.
+    async fn boards() -> Boards {
+        let config = Config { score_keys: Some(String::from("k:secret")), ..Config::default() };
+        let store = Storage::Memory.scores("scores", SortOrder::Descending).await.unwrap();
+        let tied = ["alice", "bob", "carol", "dave", "erin", "frank", "golf"];
+        let entries = [("leader", 200)].into_iter()
+            .chain(tied.into_iter().map(|name| (name, 100)))
+            .chain([("last", 50)]);
+        for (index, (name, score)) in entries.enumerate() {
+            store.insert(Entry {
+                id: None,
+                player_id: None,
+                name: name.to_string(),
+                score,
+                datetime: Utc::now() - Duration::minutes(20) + Duration::minutes(index as i64),
+                shadow: false,
+                status: EntryStatus::Published,
+                has_replay: false,
+                ip_hash: None,
+            }).await.unwrap();
+        }
+        let mut boards = Boards::new("gurtle");
+        let config = BoardConfig::new("gurtle", &BoardSettings::default(), &config).unwrap();
+        boards.insert(Board { name: String::from("gurtle"), config, store });
+        boards
+    }
+
+    async fn get(uri: &str) -> ServiceResponse {
+        let players: web::Data<dyn PlayerStore> = web::Data::from(Storage::Memory.players().await.unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(boards().await))
+                .app_data(players)
+                .configure(board_routes),
+        ).await;
+        test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await
+    }
+
+    fn summary(entries: &[Ranked]) -> Vec<(u64, &str)> {
+        entries.iter().map(|entry| (entry.rank, entry.name.as_str())).collect()
+    }
+
+    #[actix_web::test]
+    async fn equal_scores_share_their_rank() {
+        let response = get("/scores/alltime?limit=3").await;
+        assert_eq!(response.headers().get("x-total-count").unwrap(), "9");
+        let entries: Vec<Ranked> = test::read_body_json(response).await;
+        assert_eq!(summary(&entries), [(1, "leader"), (2, "golf"), (2, "frank")]);
+        // Datetimes keep the format clients parsed before they were typed.
+        assert!(parse_legacy_datetime(&entries[0].datetime).is_some());
+    }
+
+    #[actix_web::test]
+    async fn pages_starting_among_ties_keep_their_rank() {
+        let entries: Vec<Ranked> = test::read_body_json(get("/scores/alltime?offset=6&limit=3").await).await;
+        assert_eq!(summary(&entries), [(2, "bob"), (2, "alice"), (9, "last")]);
+    }
+
+    #[actix_web::test]
+    async fn offsets_have_to_fit_the_stores() {
+        assert_eq!(get(&format!("/scores/alltime?offset={}", u64::MAX)).await.status(), 400);
+        let entries: Vec<Ranked> = test::read_body_json(get(&format!("/scores/alltime?offset={}", i64::MAX)).await).await;
+        assert!(entries.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn around_a_name_contains_it_among_many_ties() {
+        let around: AroundBody = test::read_body_json(get("/around/alltime?name=alice&count=1").await).await;
+        assert_eq!(around.position, 2);
+        assert_eq!(summary(&around.entries), [(2, "bob"), (2, "alice")]);
+        let around: AroundBody = test::read_body_json(get("/around/alltime?score=100&count=1").await).await;
+        assert_eq!(around.position, 2);
+        assert_eq!(summary(&around.entries), [(1, "leader"), (2, "golf")]);
+    }
+
+    // Everything submissions to a board named gurtle need, with the state
+    // tests look into.
+    struct Submissions {
+        boards: web::Data<Boards>,
+        sessions: web::Data<Sessions>,
+        limits: web::Data<RateLimits>,
+        players: web::Data<dyn PlayerStore>,
+        bans: web::Data<dyn BanStore>,
+        audit: web::Data<dyn AuditStore>,
+    }
+
+    impl Submissions {
+
+        async fn new(config: BoardConfig, limits: RateLimitConfig) -> Submissions {
+            let mut boards = Boards::new("gurtle");
+            let store = Storage::Memory.scores("scores", SortOrder::Descending).await.unwrap();
+            boards.insert(Board { name: String::from("gurtle"), config, store });
+            Submissions {
+                boards: web::Data::new(boards),
+                sessions: web::Data::new(Sessions::new(Duration::hours(1), Duration::zero())),
+                limits: web::Data::new(set_up_rate_limits(&limits).unwrap()),
+                players: web::Data::from(Storage::Memory.players().await.unwrap()),
+                bans: web::Data::from(Storage::Memory.bans().await.unwrap()),
+                audit: web::Data::from(Storage::Memory.audit().await.unwrap()),
+            }
+        }
+
+        fn app(&self) -> App<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error, InitError = ()>> {
+            let policy = NamePolicy { min_length: 1, max_length: 16, filter: None };
+            App::new()
+                .app_data(web::JsonConfig::default().error_handler(errors::json_error))
+                .app_data(self.boards.clone())
+                .app_data(self.sessions.clone())
+                .app_data(self.limits.clone())
+                .app_data(self.players.clone())
+                .app_data(self.bans.clone())
+                .app_data(self.audit.clone())
+                .app_data(web::Data::new(policy))
+                .app_data(web::Data::new(AdminKey::new("admin")))
+                .configure(admin_routes)
+                .configure(player_routes)
+                .configure(board_routes)
+        }
+
+        fn session(&self) -> String {
+            self.sessions.start("gurtle", Utc::now()).token
+        }
+
+        fn store(&self) -> Arc<dyn store::ScoreStore> {
+            self.boards.get(None).unwrap().store.clone()
+        }
+
+    }
+
+    fn board_config(settings: BoardSettings) -> BoardConfig {
+        let config = Config { score_keys: Some(String::from("k:secret")), ..Config::default() };
+        BoardConfig::new("gurtle", &settings, &config).unwrap()
+    }
+
+    fn signed(session: &str, name: &str, score: i32) -> SubmittedEntry {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(score_message(session, name, score).as_bytes());
+        SubmittedEntry {
+            name: name.to_string(),
+            score,
+            session: session.to_string(),
+            key_id: String::from("k"),
+            hash: hex::encode(mac.finalize().into_bytes()),
+            replay: None,
+        }
+    }
+
+    fn submit(entry: SubmittedEntry) -> test::TestRequest {
+        test::TestRequest::post().uri("/submitscore").set_json(entry)
+    }
+
+    #[actix_web::test]
+    async fn limited_submissions_keep_their_session() {
+        let limits = RateLimitConfig { names: String::from("1/3600"), ..RateLimitConfig::default() };
+        let submissions = Submissions::new(board_config(BoardSettings::default()), limits).await;
+        let app = test::init_service(submissions.app()).await;
+        let session = submissions.session();
+        assert_eq!(test::call_service(&app, submit(signed(&session, "alice", 10)).to_request()).await.status(), 200);
+        let session = submissions.session();
+        let response = test::call_service(&app, submit(signed(&session, "alice", 20)).to_request()).await;
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().contains_key("retry-after"));
+        assert_eq!(test::call_service(&app, submit(signed(&session, "bob", 20)).to_request()).await.status(), 200);
+        assert_eq!(submissions.store().count(&ScoreQuery::default()).await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn registering_leaves_session_starts_alone() {
+        let limits = RateLimitConfig { sessions: String::from("1/60"), registrations: String::from("1/60"), ..RateLimitConfig::default() };
+        let submissions = Submissions::new(board_config(BoardSettings::default()), limits).await;
+        let app = test::init_service(submissions.app()).await;
+        let register = |name: &str| test::TestRequest::post()
+            .uri("/players/register")
+            .set_json(HashMap::from([("name", name)]))
+            .to_request();
+        assert_eq!(test::call_service(&app, register("alice")).await.status(), 201);
+        assert_eq!(test::call_service(&app, register("bob")).await.status(), 429);
+        let start = test::TestRequest::post().uri("/session/start").to_request();
+        assert_eq!(test::call_service(&app, start).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn guesses_at_the_admin_key_are_limited() {
+        let limits = RateLimitConfig { admin: String::from("2/60"), ..RateLimitConfig::default() };
+        let submissions = Submissions::new(board_config(BoardSettings::default()), limits).await;
+        let app = test::init_service(submissions.app()).await;
+        let guess = || test::TestRequest::get()
+            .uri("/admin/bans")
+            .insert_header(("authorization", "Bearer guess"))
+            .to_request();
+        for _ in 0..2 {
+            let err = test::try_call_service(&app, guess()).await.err().unwrap();
+            assert_eq!(err.as_response_error().status_code(), 401);
+        }
+        for _ in 0..10 {
+            assert_eq!(test::call_service(&app, guess()).await.status(), 429);
+        }
+        assert_eq!(submissions.audit.search(&store::AuditFilter::default(), 0, 100).await.unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn shadow_banned_submitters_keep_seeing_their_scores() {
+        let submissions = Submissions::new(board_config(BoardSettings::default()), RateLimitConfig::default()).await;
+        let app = test::init_service(submissions.app()).await;
+        let from = |address: &str| format!("{}:4000", address).parse().unwrap();
+        let admin = |request: test::TestRequest| request.insert_header(("authorization", "Bearer admin")).to_request();
+        let ban = |target: &str, value: &str| admin(test::TestRequest::post()
+            .uri("/admin/bans")
+            .set_json(HashMap::from([("target", target), ("value", value), ("mode", "shadow")])));
+        let count = |address: &str| test::TestRequest::get().uri("/scores/alltime").peer_addr(from(address)).to_request();
+
+        assert_eq!(test::call_service(&app, ban("ip", "10.0.0.1")).await.status(), 201);
+        let session = submissions.session();
+        let submit = test::TestRequest::post()
+            .uri("/submitscore")
+            .peer_addr(from("10.0.0.1"))
+            .set_json(signed(&session, "b\u{03BF}b", 10))
+            .to_request();
+        assert_eq!(test::call_service(&app, submit).await.status(), 200);
+        let entries: Vec<Ranked> = test::call_and_read_body_json(&app, count("10.0.0.1")).await;
+        assert_eq!(entries.len(), 1);
+        let entries: Vec<Ranked> = test::call_and_read_body_json(&app, count("10.0.0.2")).await;
+        assert!(entries.is_empty());
+
+        // Bans by name find look-alike spellings of it, and lifting one ban
+        // leaves entries another still covers shadowed.
+        assert_eq!(test::call_service(&app, ban("name", "bob")).await.status(), 201);
+        let lift = |path: &str| admin(test::TestRequest::delete().uri(path));
+        assert_eq!(test::call_service(&app, lift("/admin/bans/ip/10.0.0.1")).await.status(), 204);
+        let entries: Vec<Ranked> = test::call_and_read_body_json(&app, count("10.0.0.2")).await;
+        assert!(entries.is_empty());
+        assert_eq!(test::call_service(&app, lift("/admin/bans/name/bob")).await.status(), 204);
+        let entries: Vec<Ranked> = test::call_and_read_body_json(&app, count("10.0.0.2")).await;
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct ErrorBody {
+        code: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct ReviewBody {
+        review_id: String,
+        status: EntryStatus,
+    }
+
+    // Accepts replays that spell out their score.
+    struct SpelledOut;
+
+    #[async_trait::async_trait]
+    impl replays::ReplayVerifier for SpelledOut {
+        async fn verify(&self, _: &str, score: i32, replay: &[u8]) -> Result<(), VerifyError> {
+            match replay == score.to_string().as_bytes() {
+                true => Ok(()),
+                false => Err(VerifyError::Mismatch(String::from("Different score"))),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn accepts_signed_scores_once_per_session() {
+        let submissions = Submissions::new(board_config(BoardSettings::default()), RateLimitConfig::default()).await;
+        let app = test::init_service(submissions.app()).await;
+        let session = submissions.session();
+        let response = test::call_service(&app, submit(signed(&session, "alice", 10)).to_request()).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(test::read_body(response).await, "Score added");
+
+        let again: ErrorBody = test::read_body_json(test::call_service(&app, submit(signed(&session, "alice", 10)).to_request()).await).await;
+        assert_eq!(again.code, "invalid_session");
+        let forged = SubmittedEntry { score: 1000, ..signed(&submissions.session(), "alice", 10) };
+        let response = test::call_service(&app, submit(forged).to_request()).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(test::read_body_json::<ErrorBody, _>(response).await.code, "invalid_signature");
+        assert_eq!(submissions.store().count(&ScoreQuery::default()).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn holds_suspicious_scores_for_review() {
+        let settings = BoardSettings { suspicious_score: Some(100), ..BoardSettings::default() };
+        let submissions = Submissions::new(board_config(settings), RateLimitConfig::default()).await;
+        let app = test::init_service(submissions.app()).await;
+        let response = test::call_service(&app, submit(signed(&submissions.session(), "alice", 150)).to_request()).await;
+        assert_eq!(response.status(), 202);
+        let held: ReviewBody = test::read_body_json(response).await;
+        assert_eq!(held.status, EntryStatus::Pending);
+        let review = test::TestRequest::get().uri(&format!("/reviews/{}", held.review_id)).to_request();
+        let review: ReviewBody = test::call_and_read_body_json(&app, review).await;
+        assert_eq!(review.status, EntryStatus::Pending);
+        let scores = test::TestRequest::get().uri("/scores/alltime").to_request();
+        let entries: Vec<Ranked> = test::call_and_read_body_json(&app, scores).await;
+        assert!(entries.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn rejects_banned_submitters() {
+        let submissions = Submissions::new(board_config(BoardSettings::default()), RateLimitConfig::default()).await;
+        let app = test::init_service(submissions.app()).await;
+        submissions.bans.add(store::Ban {
+            target: BanTarget::Name,
+            value: fold("alice"),
+            mode: BanMode::Ban,
+            reason: None,
+            created: Utc::now(),
+        }).await.unwrap();
+        let response = test::call_service(&app, submit(signed(&submissions.session(), "Alice", 10)).to_request()).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(test::read_body_json::<ErrorBody, _>(response).await.code, "banned");
+        let response = test::call_service(&app, submit(signed(&submissions.session(), "bob", 10)).to_request()).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn rejects_replays_of_other_scores() {
+        let mut config = board_config(BoardSettings::default());
+        config.verifier = Some(Box::new(SpelledOut));
+        let submissions = Submissions::new(config, RateLimitConfig::default()).await;
+        let app = test::init_service(submissions.app()).await;
+        let with_replay = |score: i32, replay: &str| SubmittedEntry {
+            replay: Some(base64::engine::general_purpose::STANDARD.encode(replay)),
+            ..signed(&submissions.session(), "alice", score)
+        };
+        assert_eq!(test::call_service(&app, submit(with_replay(20, "20")).to_request()).await.status(), 200);
+        let response = test::call_service(&app, submit(with_replay(30, "20")).to_request()).await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(test::read_body_json::<ErrorBody, _>(response).await.code, "replay_mismatch");
+    }
+
+    #[actix_web::test]
+    async fn caps_submissions_per_hour() {
+        let settings = BoardSettings { max_submissions_per_hour: Some(1), ..BoardSettings::default() };
+        let submissions = Submissions::new(board_config(settings), RateLimitConfig::default()).await;
+        let app = test::init_service(submissions.app()).await;
+        assert_eq!(test::call_service(&app, submit(signed(&submissions.session(), "alice", 10)).to_request()).await.status(), 200);
+        let response = test::call_service(&app, submit(signed(&submissions.session(), "alice", 20)).to_request()).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(test::read_body_json::<ErrorBody, _>(response).await.code, "rate_limited");
+        assert_eq!(test::call_service(&app, submit(signed(&submissions.session(), "bob", 20)).to_request()).await.status(), 200);
+    }
+
+}
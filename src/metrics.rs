@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    get, middleware::Next, web, Error, HttpResponse,
+};
+use prometheus::{histogram_opts, opts, Encoder, HistogramVec, IntCounterVec, Registry, TextEncoder};
+
+use crate::store::StoreError;
+
+// Requests that didn't reach a route, or were turned down by middleware
+// before their route is known.
+const OTHER_ROUTE: &str = "other";
+
+struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    submissions: IntCounterVec,
+    db_duration: HistogramVec,
+    db_errors: IntCounterVec,
+}
+
+impl Metrics {
+
+    fn new() -> Metrics {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            requests: IntCounterVec::new(
+                opts!("gurtle_http_requests_total", "HTTP requests by route and status"),
+                &["route", "status"],
+            ).expect("Metric is valid"),
+            request_duration: HistogramVec::new(
+                histogram_opts!("gurtle_http_request_duration_seconds", "Time to answer HTTP requests by route"),
+                &["route"],
+            ).expect("Metric is valid"),
+            submissions: IntCounterVec::new(
+                opts!("gurtle_submissions_total", "Score submissions by board and outcome: accepted, held or the error code"),
+                &["game", "outcome"],
+            ).expect("Metric is valid"),
+            db_duration: HistogramVec::new(
+                histogram_opts!(
+                    "gurtle_db_operation_duration_seconds",
+                    "Time taken by storage operations",
+                    vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]
+                ),
+                &["store", "operation"],
+            ).expect("Metric is valid"),
+            db_errors: IntCounterVec::new(
+                opts!("gurtle_db_errors_total", "Failed storage operations"),
+                &["store", "operation"],
+            ).expect("Metric is valid"),
+        };
+        metrics.registry.register(Box::new(metrics.requests.clone())).expect("Metric names are unique");
+        metrics.registry.register(Box::new(metrics.request_duration.clone())).expect("Metric names are unique");
+        metrics.registry.register(Box::new(metrics.submissions.clone())).expect("Metric names are unique");
+        metrics.registry.register(Box::new(metrics.db_duration.clone())).expect("Metric names are unique");
+        metrics.registry.register(Box::new(metrics.db_errors.clone())).expect("Metric names are unique");
+        metrics
+    }
+
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+// Counts and times requests by the name of their route, which is the name of
+// the handler, e.g. get_scores.
+pub async fn track(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let start = Instant::now();
+    let result = next.call(req).await;
+    let elapsed = start.elapsed().as_secs_f64();
+    let (route, status) = match &result {
+        Ok(response) => (response.request().match_name().unwrap_or(OTHER_ROUTE), response.status()),
+        Err(err) => (OTHER_ROUTE, err.as_response_error().status_code()),
+    };
+    METRICS.requests.with_label_values(&[route, status.as_str()]).inc();
+    METRICS.request_duration.with_label_values(&[route]).observe(elapsed);
+    result
+}
+
+// `outcome` is "accepted", "held" or the code of the error the submission
+// was rejected with.
+pub fn record_submission(game: &str, outcome: &str) {
+    METRICS.submissions.with_label_values(&[game, outcome]).inc();
+}
+
+// Times a storage operation and counts it if it fails.
+pub async fn observe_db<T>(store: &str, operation: &str, future: impl Future<Output = Result<T, StoreError>>) -> Result<T, StoreError> {
+    let start = Instant::now();
+    let result = future.await;
+    METRICS.db_duration.with_label_values(&[store, operation]).observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        METRICS.db_errors.with_label_values(&[store, operation]).inc();
+    }
+    result
+}
+
+#[get("/metrics")]
+async fn get_metrics() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder.encode(&METRICS.registry.gather(), &mut body).expect("Writing to a Vec can't fail");
+    HttpResponse::Ok().content_type(encoder.format_type()).body(body)
+}
+
+pub fn metrics_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_metrics);
+}
@@ -0,0 +1,174 @@
+use std::fs;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::confusable_detection::skeleton;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum NameError {
+    #[error("Name is empty")]
+    Empty,
+    #[error("Name should be at least {0} characters long")]
+    TooShort(usize),
+    #[error("Name should be at most {0} characters long")]
+    TooLong(usize),
+    #[error("Name contains control or invisible characters")]
+    InvalidCharacter,
+    #[error("Name is not allowed")]
+    Blocked,
+}
+
+// The display form of a name: NFKC normalized, so e.g. fullwidth letters
+// become plain ones, with runs of whitespace collapsed to single spaces.
+pub fn normalize(name: &str) -> String {
+    name.nfkc().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// The form names are compared in. Case and characters that look alike, like
+// "I" and "l" or Cyrillic "а" and Latin "a", fold to the same key.
+pub fn fold(name: &str) -> String {
+    // Skeletons map to look-alikes in either case, e.g. Cyrillic "В" to "B"
+    // but leave lowercase "в" alone, so fold before and after lowercasing.
+    let skeleton_of = |name: &str| skeleton(name).collect::<String>();
+    skeleton_of(&skeleton_of(&normalize(name)).to_lowercase())
+}
+
+// Format characters like zero-width spaces would let otherwise equal names
+// look different, or hide words from the filter.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{00AD}' | '\u{034F}' | '\u{061C}' | '\u{115F}' | '\u{1160}' | '\u{17B4}' | '\u{17B5}'
+        | '\u{180B}'..='\u{180F}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{206F}'
+        | '\u{3164}' | '\u{FE00}'..='\u{FE0F}' | '\u{FEFF}' | '\u{FFA0}' | '\u{FFF0}'..='\u{FFFB}')
+}
+
+// Decides which names are acceptable, given their folded form.
+pub trait NameFilter: Send + Sync {
+    fn allows(&self, folded: &str) -> bool;
+}
+
+// Rejects names containing any of a list of words, ignoring case, look-alike
+// characters and anything between the letters, so "B.a.d" matches "bad".
+// Words only match whole words of a name, so "ass" leaves "Glass" alone;
+// words written as `*word*` match anywhere in it.
+pub struct WordList {
+    words: Vec<Word>,
+}
+
+struct Word {
+    letters: String,
+    anywhere: bool,
+}
+
+fn letters(folded: &str) -> String {
+    folded.chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+impl WordList {
+
+    // One word per line; empty lines and lines starting with # are skipped.
+    pub fn load(path: &str) -> Result<WordList, String> {
+        let contents = fs::read_to_string(path).map_err(|err| format!("Can't read {}: {}", path, err))?;
+        Ok(WordList::parse(&contents))
+    }
+
+    fn parse(contents: &str) -> WordList {
+        let words = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.strip_prefix('*').and_then(|line| line.strip_suffix('*')) {
+                Some(word) => Word { letters: letters(&fold(word)), anywhere: true },
+                None => Word { letters: letters(&fold(line)), anywhere: false },
+            })
+            .filter(|word| !word.letters.is_empty())
+            .collect();
+        WordList { words }
+    }
+
+}
+
+impl NameFilter for WordList {
+    fn allows(&self, folded: &str) -> bool {
+        // Where the words of the name start and end in its letters. Single
+        // letters count as words, so spelled out words still match.
+        let mut boundaries = vec![0];
+        for word in folded.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+            boundaries.push(boundaries[boundaries.len() - 1] + word.len());
+        }
+        let letters = letters(folded);
+        !self.words.iter().any(|word| {
+            letters.match_indices(word.letters.as_str()).any(|(start, _)| {
+                word.anywhere || boundaries.contains(&start) && boundaries.contains(&(start + word.letters.len()))
+            })
+        })
+    }
+}
+
+pub struct NamePolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub filter: Option<Box<dyn NameFilter>>,
+}
+
+impl NamePolicy {
+
+    // Returns the normalized name to store and display.
+    pub fn check(&self, name: &str) -> Result<String, NameError> {
+        let name = normalize(name);
+        if name.is_empty() {
+            return Err(NameError::Empty);
+        }
+        if name.chars().any(|c| c.is_control() || is_invisible(c)) {
+            return Err(NameError::InvalidCharacter);
+        }
+        let length = name.chars().count();
+        if length < self.min_length {
+            return Err(NameError::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(NameError::TooLong(self.max_length));
+        }
+        if self.filter.as_ref().is_some_and(|filter| !filter.allows(&fold(&name))) {
+            return Err(NameError::Blocked);
+        }
+        Ok(name)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(words: &[&str]) -> NamePolicy {
+        NamePolicy { min_length: 2, max_length: 8, filter: Some(Box::new(WordList::parse(&words.join("\n")))) }
+    }
+
+    #[test]
+    fn folds_case_and_look_alikes() {
+        assert_eq!(fold("Alice"), fold("alice"));
+        assert_eq!(fold("ＢＯＢ"), fold("bob"));
+        assert_eq!(fold("\u{0430}lice"), fold("alice"));
+        assert_ne!(fold("alice"), fold("alicia"));
+    }
+
+    #[test]
+    fn checks_names() {
+        let policy = policy(&["bad"]);
+        assert_eq!(policy.check("  Ada   Byr ").unwrap(), "Ada Byr");
+        assert!(matches!(policy.check("   "), Err(NameError::Empty)));
+        assert!(matches!(policy.check("a"), Err(NameError::TooShort(2))));
+        assert!(matches!(policy.check("abcdefghi"), Err(NameError::TooLong(8))));
+        assert!(matches!(policy.check("ab\u{200B}c"), Err(NameError::InvalidCharacter)));
+        assert!(matches!(policy.check("B.a.D"), Err(NameError::Blocked)));
+        assert!(policy.check("x\u{0431}ad").is_ok());
+    }
+
+    #[test]
+    fn blocks_whole_words_unless_asked_otherwise() {
+        let policy = policy(&["# comment", "ass", "*bad*"]);
+        assert!(policy.check("Glass").is_ok());
+        assert!(policy.check("Cassie").is_ok());
+        assert!(matches!(policy.check("ass hat"), Err(NameError::Blocked)));
+        assert!(matches!(policy.check("A S S"), Err(NameError::Blocked)));
+        assert!(matches!(policy.check("xXbadXx"), Err(NameError::Blocked)));
+    }
+
+}
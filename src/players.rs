@@ -0,0 +1,104 @@
+use serde::{Serialize, Deserialize};
+use actix_web::{dev::Payload, http::header::AUTHORIZATION, post, web, FromRequest, HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use chrono::offset::Utc;
+
+use crate::errors::ApiError;
+use crate::names::{fold, NamePolicy};
+use crate::ratelimit::{RateLimit, RateLimits};
+use crate::store::{Player, PlayerStore};
+
+#[derive(Deserialize, Debug)]
+struct Registration {
+    name: String,
+}
+
+// The token is only ever returned here; the server keeps just its hash.
+#[derive(Serialize, Debug)]
+struct Registered {
+    id: String,
+    name: String,
+    token: String,
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut random = vec![0; bytes];
+    rand::thread_rng().fill_bytes(&mut random);
+    hex::encode(random)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Registration has a limit of its own per client IP.
+#[post("/players/register", wrap = "RateLimit::registrations()")]
+async fn register(players: web::Data<dyn PlayerStore>, policy: web::Data<NamePolicy>, registration: web::Json<Registration>) -> Result<HttpResponse, ApiError> {
+    let name = policy.check(&registration.name)?;
+    let token = random_hex(32);
+    let player = Player {
+        id: random_hex(16),
+        name_key: fold(&name),
+        name,
+        token_hash: hash_token(&token),
+        created: Utc::now(),
+    };
+    let registered = Registered { id: player.id.clone(), name: player.name.clone(), token };
+    match players.create(player).await? {
+        true => Ok(HttpResponse::Created().json(registered)),
+        false => Err(ApiError::conflict("name_taken", "Name is already taken")),
+    }
+}
+
+pub fn player_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(register);
+}
+
+// The player a request is authenticated as by an `Authorization: Bearer`
+// header, or None for anonymous requests. A header with an unknown token is
+// rejected rather than treated as anonymous.
+pub struct PlayerAuth(pub Option<Player>);
+
+impl PlayerAuth {
+
+    pub fn player_id(&self) -> Option<String> {
+        self.0.as_ref().map(|player| player.id.clone())
+    }
+
+    // Who is looking at a board, so shadowed entries are shown to whoever
+    // submitted them: players by their id, anonymous clients by the hash of
+    // their address, which anonymous entries are stored with.
+    pub fn viewer(&self, req: &HttpRequest) -> Option<String> {
+        self.player_id().or_else(|| {
+            req.app_data::<web::Data<RateLimits>>()
+                .and_then(|limits| limits.client_ip(req))
+                .map(|ip| hash_token(&ip))
+        })
+    }
+
+}
+
+impl FromRequest for PlayerAuth {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<PlayerAuth, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let header = req.headers().get(AUTHORIZATION)
+            .map(|value| value.to_str().ok().and_then(|value| value.strip_prefix("Bearer ")).map(str::trim).map(String::from));
+        let players = req.app_data::<web::Data<dyn PlayerStore>>().map(|players| players.clone().into_inner());
+        Box::pin(async move {
+            let token = match header {
+                None => return Ok(PlayerAuth(None)),
+                Some(None) => return Err(ApiError::unauthorized("invalid_token", "Authorization should be a bearer token")),
+                Some(Some(token)) => token,
+            };
+            let players = players.ok_or_else(|| ApiError::internal("No player store configured"))?;
+            match players.by_token_hash(&hash_token(&token)).await? {
+                Some(player) => Ok(PlayerAuth(Some(player))),
+                None => Err(ApiError::unauthorized("invalid_token", "Unknown player token")),
+            }
+        })
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpRequest, ResponseError,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::audit;
+use crate::boards::Boards;
+use crate::errors::ApiError;
+use crate::metrics;
+use crate::store::{AuditKind, AuditStore};
+
+// At most this many keys are tracked. Reaching it drops the buckets that are
+// full again, which carry no information, and then the least recently used
+// ones until half are left, so evicting is rare.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+const UNKNOWN_CLIENT: &str = "unknown";
+
+// A token bucket size and refill period, written as "requests/seconds". A
+// limit of "30/60" allows bursts of 30 requests and refills the bucket over a
+// minute.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    capacity: f64,
+    period: Duration,
+}
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Limit, String> {
+        let invalid = || format!("Rate limit \"{}\" should have the form requests/seconds", value);
+        let (requests, seconds) = value.split_once('/').ok_or_else(invalid)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| invalid())?;
+        let seconds: u64 = seconds.trim().parse().map_err(|_| invalid())?;
+        if requests == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(Limit { capacity: requests as f64, period: Duration::from_secs(seconds) })
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct Limiter {
+    limit: Limit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Limiter {
+
+    pub fn new(limit: Limit) -> Limiter {
+        Limiter { limit, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    fn refill_rate(&self) -> f64 {
+        self.limit.capacity / self.limit.period.as_secs_f64()
+    }
+
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        let rate = self.refill_rate();
+        let capacity = self.limit.capacity;
+        buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity);
+        let keep = MAX_TRACKED_KEYS / 2;
+        if buckets.len() > keep {
+            let mut updated: Vec<Instant> = buckets.values().map(|bucket| bucket.updated).collect();
+            let drop = updated.len() - keep;
+            let (_, cutoff, _) = updated.select_nth_unstable(drop);
+            let cutoff = *cutoff;
+            buckets.retain(|_, bucket| bucket.updated >= cutoff);
+        }
+    }
+
+    // Takes a token from the bucket for `key`. If it is empty, returns how
+    // long until the next token is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let rate = self.refill_rate();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            self.evict(&mut buckets, now);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: self.limit.capacity, updated: now });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refilled).min(self.limit.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    Reads,
+    Sessions,
+    Submissions,
+    Registrations,
+    Admin,
+}
+
+pub struct RateLimits {
+    pub reads: Limiter,
+    pub sessions: Limiter,
+    pub submissions: Limiter,
+    pub registrations: Limiter,
+    // All requests to the admin API, so its key can't be guessed at speed.
+    pub admin: Limiter,
+    pub names: Limiter,
+    // Header a trusted reverse proxy puts the client address in, e.g.
+    // X-Forwarded-For. Without it the peer address is used.
+    pub proxy_header: Option<String>,
+}
+
+impl RateLimits {
+
+    fn limiter(&self, class: RouteClass) -> &Limiter {
+        match class {
+            RouteClass::Reads => &self.reads,
+            RouteClass::Sessions => &self.sessions,
+            RouteClass::Submissions => &self.submissions,
+            RouteClass::Registrations => &self.registrations,
+            RouteClass::Admin => &self.admin,
+        }
+    }
+
+    // The rightmost address in the proxy header is the one our proxy saw;
+    // anything left of it was supplied by the client and can be forged.
+    // Requests without the header, e.g. sent around the proxy, fall back to
+    // the peer address.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<String> {
+        let forwarded = self.proxy_header.as_ref().and_then(|header| {
+            req.headers().get(header.as_str())
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .map(|address| address.trim().to_string())
+                .filter(|address| !address.is_empty())
+        });
+        forwarded.or_else(|| req.peer_addr().map(|address| address.ip().to_string()))
+    }
+
+}
+
+// Per-route middleware limiting requests per client IP, e.g.
+// `#[get("/path", wrap = "RateLimit::reads()")]`. The limits themselves are
+// read from the `RateLimits` app data.
+pub struct RateLimit {
+    class: RouteClass,
+}
+
+impl RateLimit {
+
+    pub fn reads() -> RateLimit {
+        RateLimit { class: RouteClass::Reads }
+    }
+
+    pub fn sessions() -> RateLimit {
+        RateLimit { class: RouteClass::Sessions }
+    }
+
+    pub fn submissions() -> RateLimit {
+        RateLimit { class: RouteClass::Submissions }
+    }
+
+    pub fn registrations() -> RateLimit {
+        RateLimit { class: RouteClass::Registrations }
+    }
+
+    pub fn admin() -> RateLimit {
+        RateLimit { class: RouteClass::Admin }
+    }
+
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service, class: self.class }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    class: RouteClass,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Requests without a known address share one bucket rather than
+        // going unlimited.
+        let limited = req.app_data::<web::Data<RateLimits>>().and_then(|limits| {
+            let ip = limits.client_ip(req.request()).unwrap_or_else(|| String::from(UNKNOWN_CLIENT));
+            limits.limiter(self.class).check(&ip).err()
+        });
+        if let Some(retry_after) = limited {
+            let err = ApiError::rate_limited(retry_after);
+            let response = err.error_response().map_into_right_body();
+            if self.class != RouteClass::Submissions {
+                return Box::pin(ready(Ok(req.into_response(response))));
+            }
+            // Submissions turned down here never reach the handler that
+            // records their outcome and audits them.
+            let board = req.app_data::<web::Data<Boards>>().and_then(|boards| boards.get(req.match_info().get("game")));
+            let audit_log = req.app_data::<web::Data<dyn AuditStore>>().cloned();
+            let mut event = audit::request_event(req.request(), AuditKind::Submission, "submit");
+            event.reason = Some(err.message().to_string());
+            return Box::pin(async move {
+                if let Some(board) = board {
+                    metrics::record_submission(&board.name, err.code());
+                    event.game = Some(board.name.clone());
+                }
+                if let Some(audit_log) = audit_log {
+                    audit::record(audit_log.as_ref(), event).await;
+                }
+                Ok(req.into_response(response))
+            });
+        }
+        let response = self.service.call(req);
+        Box::pin(async move {
+            Ok(response.await?.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_tokens_until_the_bucket_is_empty() {
+        let limiter = Limiter::new("2/60".parse().unwrap());
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_ok());
+        let retry_after = limiter.check("a").unwrap_err();
+        assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+        assert!(limiter.check("b").is_ok());
+    }
+
+    #[test]
+    fn parses_limits() {
+        assert!("30/60".parse::<Limit>().is_ok());
+        assert!("0/60".parse::<Limit>().is_err());
+        assert!("30".parse::<Limit>().is_err());
+        assert!("30/0".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn tracks_a_bounded_number_of_keys() {
+        let limiter = Limiter::new("1/3600".parse().unwrap());
+        for key in 0..MAX_TRACKED_KEYS * 3 {
+            assert!(limiter.check(&key.to_string()).is_ok());
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_TRACKED_KEYS);
+        }
+        // The most recent keys are still limited.
+        assert!(limiter.check(&(MAX_TRACKED_KEYS * 3 - 1).to_string()).is_err());
+    }
+
+}
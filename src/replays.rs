@@ -0,0 +1,139 @@
+use std::io::{Read, Write};
+use std::process::{Output, Stdio};
+use std::time::Duration;
+use actix_web::{http::header::ACCEPT_ENCODING, HttpRequest};
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::audit;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("Replay is not valid base64")]
+    Encoding,
+    #[error("Replay is larger than {0} bytes")]
+    TooLarge(usize),
+}
+
+// Decodes a base64 encoded replay of at most `max_size` bytes. Replays that
+// are obviously too large are turned down before decoding them.
+pub fn decode(encoded: &str, max_size: usize) -> Result<Vec<u8>, ReplayError> {
+    if encoded.len() / 4 * 3 > max_size + 3 {
+        return Err(ReplayError::TooLarge(max_size));
+    }
+    let replay = BASE64_STANDARD.decode(encoded).map_err(|_| ReplayError::Encoding)?;
+    if replay.len() > max_size {
+        return Err(ReplayError::TooLarge(max_size));
+    }
+    Ok(replay)
+}
+
+// Replays are stored gzip compressed.
+pub fn compress(replay: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(replay).expect("Writing to a Vec can't fail");
+    encoder.finish().expect("Writing to a Vec can't fail")
+}
+
+pub fn decompress(stored: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut replay = Vec::new();
+    GzDecoder::new(stored).read_to_end(&mut replay)?;
+    Ok(replay)
+}
+
+// Whether the client takes gzip encoded responses, so stored replays can be
+// sent as they are. `gzip;q=0` explicitly refuses them.
+pub fn accepts_gzip(req: &HttpRequest) -> bool {
+    req.headers().get_all(ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let refused = |param: &str| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0);
+            parts.next().is_some_and(|name| name.eq_ignore_ascii_case("gzip")) && !parts.any(refused)
+        })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    // The replay was checked and does not produce the submitted score.
+    #[error("Replay does not match the score: {0}")]
+    Mismatch(String),
+    // The replay could not be checked at all.
+    #[error("Replay could not be verified: {0}")]
+    Failed(String),
+}
+
+// Re-simulates the input replay of a run to check that it produces the
+// submitted score.
+#[async_trait]
+pub trait ReplayVerifier: Send + Sync {
+    async fn verify(&self, game: &str, score: i32, replay: &[u8]) -> Result<(), VerifyError>;
+}
+
+// Runs a headless verifier binary as `<command> <game> <score>` with the
+// replay on stdin. Exit status 0 accepts the replay and 1 rejects it, with the
+// first line of stdout as the reason. Any other status, or running longer
+// than `timeout`, is a failure of the verifier. Its stderr goes to ours.
+pub struct SubprocessVerifier {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl SubprocessVerifier {
+
+    // `command` is the program followed by arguments of its own, separated
+    // by whitespace.
+    pub fn parse(command: &str, timeout: Duration) -> Result<SubprocessVerifier, String> {
+        let mut words = command.split_whitespace().map(String::from);
+        let program = words.next().ok_or("Verifier command is empty")?;
+        Ok(SubprocessVerifier { program, args: words.collect(), timeout })
+    }
+
+    async fn run(&self, game: &str, score: i32, replay: &[u8]) -> std::io::Result<Output> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .arg(game)
+            .arg(score.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // The verifier may decide before reading all of the replay, so a
+        // broken pipe is left for its exit status to explain. Writing while
+        // waiting keeps a chatty verifier from blocking on a full stdout.
+        let write = async move {
+            let _ = stdin.write_all(replay).await;
+        };
+        let (_, output) = tokio::join!(write, child.wait_with_output());
+        output
+    }
+
+}
+
+#[async_trait]
+impl ReplayVerifier for SubprocessVerifier {
+    async fn verify(&self, game: &str, score: i32, replay: &[u8]) -> Result<(), VerifyError> {
+        // Dropping the run on timeout kills the verifier.
+        let output = match tokio::time::timeout(self.timeout, self.run(game, score, replay)).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(err)) => return Err(VerifyError::Failed(format!("Can't run {}: {}", self.program, err))),
+            Err(_) => return Err(VerifyError::Failed(String::from("Verifier timed out"))),
+        };
+        match output.status.code() {
+            Some(0) => Ok(()),
+            Some(1) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let reason = stdout.lines().next().map(str::trim).filter(|line| !line.is_empty());
+                Err(VerifyError::Mismatch(audit::truncate(reason.unwrap_or("no reason given"))))
+            },
+            _ => Err(VerifyError::Failed(format!("Verifier exited with {}", output.status))),
+        }
+    }
+}
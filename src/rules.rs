@@ -0,0 +1,71 @@
+use chrono::Duration;
+
+use crate::ratelimit::Limiter;
+use crate::store::SortOrder;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RuleViolation {
+    #[error("Score is below the minimum of {0}")]
+    BelowMinimum(i32),
+    #[error("Score exceeds maximum")]
+    AboveMaximum,
+    #[error("Score is implausibly high for the session length")]
+    TooFast,
+}
+
+// Whether a score that passed the rules is published right away or held for
+// an admin to review, and why.
+pub enum Verdict {
+    Publish,
+    Hold(String),
+}
+
+// The plausibility rules of a board. Scores outside the hard limits are
+// rejected, outliers beyond the suspicious ones are held for review.
+// `max_score_rate` is in points per second of session time, so the rates are
+// only set on boards where higher scores are better.
+pub struct Rules {
+    pub min_score: Option<i32>,
+    pub max_score: Option<i32>,
+    pub max_score_rate: Option<f64>,
+    pub suspicious_score: Option<i32>,
+    pub suspicious_score_rate: Option<f64>,
+    // Submissions per player, or name for anonymous ones, and hour.
+    pub submissions: Option<Limiter>,
+}
+
+fn exceeds_rate(score: i32, session_length: Duration, rate: f64) -> bool {
+    score as f64 > rate * session_length.num_milliseconds() as f64 / 1000.0
+}
+
+impl Rules {
+
+    pub fn check(&self, order: SortOrder, score: i32, session_length: Duration) -> Result<Verdict, RuleViolation> {
+        if let Some(min) = self.min_score.filter(|min| score < *min) {
+            return Err(RuleViolation::BelowMinimum(min));
+        }
+        if self.max_score.is_some_and(|max| score > max) {
+            return Err(RuleViolation::AboveMaximum);
+        }
+        if self.max_score_rate.is_some_and(|rate| exceeds_rate(score, session_length, rate)) {
+            return Err(RuleViolation::TooFast);
+        }
+        if self.suspicious_score.is_some_and(|threshold| order.compare(score, threshold).is_lt()) {
+            return Ok(Verdict::Hold(String::from("Score is better than the suspicious threshold")));
+        }
+        if self.suspicious_score_rate.is_some_and(|rate| exceeds_rate(score, session_length, rate)) {
+            return Ok(Verdict::Hold(String::from("Score rate is suspicious")));
+        }
+        Ok(Verdict::Publish)
+    }
+
+    // Takes one of `submitter`'s submissions for this hour, or returns when
+    // the next one is allowed.
+    pub fn check_submitter(&self, submitter: &str) -> Result<(), std::time::Duration> {
+        match &self.submissions {
+            Some(limiter) => limiter.check(submitter),
+            None => Ok(()),
+        }
+    }
+
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use rand::RngCore;
+use chrono::{DateTime, Duration, offset::Utc};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Sessions that can be outstanding at once. Expired ones are dropped first,
+// then the oldest.
+const MAX_OUTSTANDING: usize = 100_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("Invalid session token")]
+    Malformed,
+    #[error("Session already used or unknown")]
+    Unknown,
+    #[error("Session expired")]
+    Expired,
+    #[error("Session too short")]
+    TooShort,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionToken {
+    pub token: String,
+    pub issued_at: DateTime<Utc>,
+}
+
+// Issues the single-use tokens a game session has to present when its score
+// is submitted. Tokens are `nonce.issued_at.mac`; the MAC stops clients from
+// forging tokens, backdating the issue time or using a token on another
+// game's board, and a token is only valid while its nonce is still
+// outstanding, so each can be consumed exactly once. Nonces are kept in
+// memory and the secret is random, so tokens are only valid on the instance
+// that issued them, until it restarts.
+pub struct Sessions {
+    secret: Vec<u8>,
+    max_age: Duration,
+    min_length: Duration,
+    outstanding: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl Sessions {
+
+    pub fn new(max_age: Duration, min_length: Duration) -> Sessions {
+        let mut secret = vec![0; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Sessions {
+            secret,
+            max_age,
+            min_length,
+            outstanding: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn mac(&self, game: &str, nonce: &str, issued_at: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}.{}.{}", game, nonce, issued_at).as_bytes());
+        mac
+    }
+
+    pub fn start(&self, game: &str, now: DateTime<Utc>) -> SessionToken {
+        let mut nonce = [0; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = hex::encode(nonce);
+        let issued_at = now.timestamp_millis();
+        let signature = hex::encode(self.mac(game, &nonce, issued_at).finalize().into_bytes());
+        let mut outstanding = self.outstanding.lock().unwrap();
+        if outstanding.len() >= MAX_OUTSTANDING {
+            self.evict(&mut outstanding, now);
+        }
+        outstanding.insert(nonce.clone(), now);
+        SessionToken {
+            token: format!("{}.{}.{}", nonce, issued_at, signature),
+            issued_at: now,
+        }
+    }
+
+    fn evict(&self, outstanding: &mut HashMap<String, DateTime<Utc>>, now: DateTime<Utc>) {
+        outstanding.retain(|_, issued| now - *issued <= self.max_age);
+        let keep = MAX_OUTSTANDING / 2;
+        if outstanding.len() > keep {
+            let mut issued: Vec<DateTime<Utc>> = outstanding.values().copied().collect();
+            let drop = issued.len() - keep;
+            let (_, cutoff, _) = issued.select_nth_unstable(drop);
+            let cutoff = *cutoff;
+            outstanding.retain(|_, issued| *issued >= cutoff);
+        }
+    }
+
+    // Validates and uses up a session token. Returns how long the session
+    // lasted, i.e. the time since the token was issued.
+    pub fn consume(&self, game: &str, token: &str, now: DateTime<Utc>) -> Result<Duration, SessionError> {
+        let mut parts = token.split('.');
+        let (Some(nonce), Some(issued_at), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(SessionError::Malformed);
+        };
+        let issued_at: i64 = issued_at.parse().map_err(|_| SessionError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| SessionError::Malformed)?;
+        self.mac(game, nonce, issued_at).verify_slice(&signature).map_err(|_| SessionError::Malformed)?;
+        let issued = self.outstanding.lock().unwrap().remove(nonce).ok_or(SessionError::Unknown)?;
+        let length = now - issued;
+        if length > self.max_age {
+            return Err(SessionError::Expired);
+        }
+        if length < self.min_length {
+            return Err(SessionError::TooShort);
+        }
+        Ok(length)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions() -> Sessions {
+        Sessions::new(Duration::minutes(10), Duration::seconds(5))
+    }
+
+    #[test]
+    fn tokens_can_be_used_once() {
+        let sessions = sessions();
+        let now = Utc::now();
+        let token = sessions.start("gurtle", now).token;
+        assert_eq!(sessions.consume("gurtle", &token, now + Duration::seconds(30)).unwrap(), Duration::seconds(30));
+        assert!(matches!(sessions.consume("gurtle", &token, now + Duration::seconds(31)), Err(SessionError::Unknown)));
+    }
+
+    #[test]
+    fn tokens_are_bound_to_their_game_and_time() {
+        let sessions = sessions();
+        let now = Utc::now();
+        let token = sessions.start("gurtle", now).token;
+        assert!(matches!(sessions.consume("other", &token, now + Duration::seconds(30)), Err(SessionError::Malformed)));
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let backdated = (now.timestamp_millis() - 60_000).to_string();
+        parts[1] = &backdated;
+        assert!(matches!(sessions.consume("gurtle", &parts.join("."), now + Duration::seconds(30)), Err(SessionError::Malformed)));
+        assert!(matches!(sessions.consume("gurtle", "garbage", now), Err(SessionError::Malformed)));
+    }
+
+    #[test]
+    fn sessions_have_to_be_long_enough_but_not_too_old() {
+        let sessions = sessions();
+        let now = Utc::now();
+        let token = sessions.start("gurtle", now).token;
+        assert!(matches!(sessions.consume("gurtle", &token, now + Duration::seconds(1)), Err(SessionError::TooShort)));
+        let token = sessions.start("gurtle", now).token;
+        assert!(matches!(sessions.consume("gurtle", &token, now + Duration::minutes(11)), Err(SessionError::Expired)));
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_sessions() {
+        let sessions = sessions();
+        let now = Utc::now();
+        let first = sessions.start("gurtle", now).token;
+        for index in 1..MAX_OUTSTANDING + 10 {
+            sessions.start("gurtle", now + Duration::milliseconds(index as i64));
+        }
+        assert!(sessions.outstanding.lock().unwrap().len() <= MAX_OUTSTANDING);
+        assert!(matches!(sessions.consume("gurtle", &first, now + Duration::seconds(30)), Err(SessionError::Unknown)));
+    }
+
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("Unknown key")]
+    UnknownKey,
+    #[error("Invalid hash")]
+    InvalidSignature,
+}
+
+// The set of secrets clients may sign scores with, by key id. Several keys
+// can be active at once so a secret can be rotated per client release.
+#[derive(Clone)]
+pub struct Keyring {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl Keyring {
+
+    // Parses a comma separated list of `key_id:secret` pairs.
+    pub fn parse(spec: &str) -> Result<Keyring, String> {
+        let mut keys = HashMap::new();
+        for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key_id, secret) = pair.split_once(':')
+                .ok_or(format!("Key \"{}\" should have the form key_id:secret", pair))?;
+            if key_id.is_empty() || secret.is_empty() {
+                return Err(format!("Key \"{}\" has an empty id or secret", pair));
+            }
+            if keys.insert(key_id.to_string(), secret.as_bytes().to_vec()).is_some() {
+                return Err(format!("Key id \"{}\" is defined twice", key_id));
+            }
+        }
+        if keys.is_empty() {
+            return Err(String::from("At least one key is required"));
+        }
+        Ok(Keyring { keys })
+    }
+
+    // Checks a hex encoded HMAC-SHA256 of `message`. The comparison of the
+    // MAC itself is constant-time.
+    pub fn verify(&self, key_id: &str, message: &str, signature: &str) -> Result<(), SignatureError> {
+        let key = self.keys.get(key_id).ok_or(SignatureError::UnknownKey)?;
+        let signature = hex::decode(signature).map_err(|_| SignatureError::InvalidSignature)?;
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        mac.verify_slice(&signature).map_err(|_| SignatureError::InvalidSignature)
+    }
+
+}
+
+// The message a client signs for a score submission. Including the session
+// token ties the signature to a single game session.
+pub fn score_message(session: &str, name: &str, score: i32) -> String {
+    format!("{}\n{}\n{}", session, name, score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &str, message: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(message.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn verifies_with_the_named_key() {
+        let keyring = Keyring::parse("v1:old, v2:new").unwrap();
+        let message = score_message("session", "alice", 100);
+        assert!(keyring.verify("v1", &message, &sign("old", &message)).is_ok());
+        assert!(keyring.verify("v2", &message, &sign("new", &message)).is_ok());
+        assert!(matches!(keyring.verify("v2", &message, &sign("old", &message)), Err(SignatureError::InvalidSignature)));
+        assert!(matches!(keyring.verify("v3", &message, &sign("new", &message)), Err(SignatureError::UnknownKey)));
+        assert!(matches!(keyring.verify("v1", &message, "not hex"), Err(SignatureError::InvalidSignature)));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("v1").is_err());
+        assert!(Keyring::parse("v1:").is_err());
+        assert!(Keyring::parse("v1:a,v1:b").is_err());
+    }
+
+}
@@ -0,0 +1,297 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use async_trait::async_trait;
+use super::{AuditEvent, AuditFilter, AuditStore, Ban, BanStore, BanTarget, Entry, EntryFilter, EntryStatus, EntryUpdate, Player, PlayerStore, ScoreQuery, ScoreStore, SortOrder, StoreError};
+
+// Keeps everything in process memory, so scores are lost on restart. Meant
+// for local development and tests that should not need a database.
+pub struct MemoryStore {
+    order: SortOrder,
+    entries: RwLock<Vec<Entry>>,
+    replays: RwLock<HashMap<String, Vec<u8>>>,
+    next_id: AtomicU64,
+}
+
+impl MemoryStore {
+
+    pub fn new(order: SortOrder) -> MemoryStore {
+        MemoryStore {
+            order,
+            entries: RwLock::new(Vec::new()),
+            replays: RwLock::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn rank_order(&self, a: &Entry, b: &Entry) -> Ordering {
+        self.order.compare(a.score, b.score).then_with(|| b.datetime.cmp(&a.datetime))
+    }
+
+    // The entries a query ranks, in no particular order.
+    fn matching<'a>(&self, entries: &'a [Entry], query: &ScoreQuery) -> Vec<&'a Entry> {
+        let in_range = entries.iter().filter(|entry| query.includes(entry));
+        if !query.best_per_player {
+            return in_range.collect();
+        }
+        let mut best: HashMap<(Option<&str>, &str), &Entry> = HashMap::new();
+        for entry in in_range {
+            best.entry(player_key(entry))
+                .and_modify(|current| if self.rank_order(entry, current).is_lt() { *current = entry })
+                .or_insert(entry);
+        }
+        best.into_values().collect()
+    }
+
+    fn set_shadow(&self, selected: impl Fn(&Entry) -> bool, shadow: bool) -> u64 {
+        let mut entries = self.entries.write().unwrap();
+        let mut changed = 0;
+        for entry in entries.iter_mut().filter(|entry| selected(entry) && entry.shadow != shadow) {
+            entry.shadow = shadow;
+            changed += 1;
+        }
+        changed
+    }
+
+}
+
+fn player_key(entry: &Entry) -> (Option<&str>, &str) {
+    (entry.player_id.as_deref(), entry.name.as_str())
+}
+
+#[async_trait]
+impl ScoreStore for MemoryStore {
+
+    async fn insert(&self, mut entry: Entry) -> Result<String, StoreError> {
+        let id = self.next_id.fetch_add(1, AtomicOrdering::Relaxed).to_string();
+        entry.id = Some(id.clone());
+        self.entries.write().unwrap().push(entry);
+        Ok(id)
+    }
+
+    async fn top(&self, query: &ScoreQuery, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let mut scores = self.matching(&entries, query);
+        scores.sort_by(|a, b| self.rank_order(a, b));
+        Ok(scores.into_iter().skip(offset as usize).take(limit as usize).cloned().collect())
+    }
+
+    async fn count(&self, query: &ScoreQuery) -> Result<u64, StoreError> {
+        let entries = self.entries.read().unwrap();
+        Ok(self.matching(&entries, query).len() as u64)
+    }
+
+    async fn best_of(&self, query: &ScoreQuery, name: &str) -> Result<Option<Entry>, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let best = entries.iter()
+            .filter(|entry| query.includes(entry) && entry.name == name)
+            .min_by(|a, b| self.rank_order(a, b));
+        Ok(best.cloned())
+    }
+
+    async fn count_better(&self, query: &ScoreQuery, score: i32) -> Result<u64, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let better = entries.iter()
+            .filter(|entry| query.includes(entry) && self.order.compare(entry.score, score).is_lt());
+        let count = match query.best_per_player {
+            true => better.map(player_key).collect::<HashSet<_>>().len(),
+            false => better.count(),
+        };
+        Ok(count as u64)
+    }
+
+    async fn count_ahead(&self, query: &ScoreQuery, entry: &Entry) -> Result<u64, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let ahead = entries.iter()
+            .filter(|other| query.includes(other) && self.rank_order(other, entry).is_lt());
+        let count = match query.best_per_player {
+            true => ahead.map(player_key).collect::<HashSet<_>>().len(),
+            false => ahead.count(),
+        };
+        Ok(count as u64)
+    }
+
+    async fn migrate_datetimes(&self) -> Result<u64, StoreError> {
+        Ok(0)
+    }
+
+    async fn search(&self, filter: &EntryFilter, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let mut found: Vec<&Entry> = entries.iter().filter(|entry| filter.matches(entry)).collect();
+        found.sort_by_key(|entry| Reverse(entry.datetime));
+        Ok(found.into_iter().skip(offset as usize).take(limit as usize).cloned().collect())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Entry>, StoreError> {
+        let entries = self.entries.read().unwrap();
+        Ok(entries.iter().find(|entry| entry.id.as_deref() == Some(id)).cloned())
+    }
+
+    async fn update(&self, id: &str, update: &EntryUpdate) -> Result<Option<Entry>, StoreError> {
+        let mut entries = self.entries.write().unwrap();
+        let Some(entry) = entries.iter_mut().find(|entry| entry.id.as_deref() == Some(id)) else {
+            return Ok(None);
+        };
+        if let Some(name) = &update.name {
+            entry.name = name.clone();
+        }
+        if let Some(score) = update.score {
+            entry.score = score;
+        }
+        Ok(Some(entry.clone()))
+    }
+
+    async fn review(&self, id: &str, status: EntryStatus) -> Result<Option<Entry>, StoreError> {
+        let mut entries = self.entries.write().unwrap();
+        let pending = entries.iter_mut()
+            .find(|entry| entry.id.as_deref() == Some(id) && entry.status == EntryStatus::Pending);
+        Ok(pending.map(|entry| {
+            entry.status = status;
+            entry.clone()
+        }))
+    }
+
+    async fn save_replay(&self, id: &str, replay: Vec<u8>) -> Result<(), StoreError> {
+        let mut entries = self.entries.write().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.id.as_deref() == Some(id)) {
+            entry.has_replay = true;
+            self.replays.write().unwrap().insert(id.to_string(), replay);
+        }
+        Ok(())
+    }
+
+    async fn replay(&self, id: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.replays.read().unwrap().get(id).cloned())
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let mut entries = self.entries.write().unwrap();
+        let before = entries.len();
+        entries.retain(|entry| entry.id.as_deref() != Some(id));
+        self.replays.write().unwrap().remove(id);
+        Ok(entries.len() < before)
+    }
+
+    async fn delete_by_name(&self, name: &str) -> Result<u64, StoreError> {
+        let mut entries = self.entries.write().unwrap();
+        let mut replays = self.replays.write().unwrap();
+        let before = entries.len();
+        entries.retain(|entry| {
+            let keep = entry.name != name;
+            if let (false, Some(id)) = (keep, &entry.id) {
+                replays.remove(id);
+            }
+            keep
+        });
+        Ok((before - entries.len()) as u64)
+    }
+
+    async fn names(&self) -> Result<Vec<String>, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let names: HashSet<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        Ok(names.into_iter().map(String::from).collect())
+    }
+
+    async fn shadow_name(&self, name: &str, shadow: bool) -> Result<u64, StoreError> {
+        Ok(self.set_shadow(|entry| entry.name == name, shadow))
+    }
+
+    async fn shadow_player(&self, player_id: &str, shadow: bool) -> Result<u64, StoreError> {
+        Ok(self.set_shadow(|entry| entry.player_id.as_deref() == Some(player_id), shadow))
+    }
+
+    async fn shadow_ip(&self, ip_hash: &str, shadow: bool) -> Result<u64, StoreError> {
+        Ok(self.set_shadow(|entry| entry.ip_hash.as_deref() == Some(ip_hash), shadow))
+    }
+
+}
+
+#[derive(Default)]
+pub struct MemoryPlayers {
+    players: RwLock<Vec<Player>>,
+}
+
+#[async_trait]
+impl PlayerStore for MemoryPlayers {
+
+    async fn create(&self, player: Player) -> Result<bool, StoreError> {
+        let mut players = self.players.write().unwrap();
+        if players.iter().any(|existing| existing.name_key == player.name_key) {
+            return Ok(false);
+        }
+        players.push(player);
+        Ok(true)
+    }
+
+    async fn by_token_hash(&self, token_hash: &str) -> Result<Option<Player>, StoreError> {
+        let players = self.players.read().unwrap();
+        Ok(players.iter().find(|player| player.token_hash == token_hash).cloned())
+    }
+
+    async fn by_name_key(&self, name_key: &str) -> Result<Option<Player>, StoreError> {
+        let players = self.players.read().unwrap();
+        Ok(players.iter().find(|player| player.name_key == name_key).cloned())
+    }
+
+}
+
+#[derive(Default)]
+pub struct MemoryBans {
+    bans: RwLock<Vec<Ban>>,
+}
+
+#[async_trait]
+impl BanStore for MemoryBans {
+
+    async fn add(&self, ban: Ban) -> Result<(), StoreError> {
+        let mut bans = self.bans.write().unwrap();
+        bans.retain(|existing| existing.target != ban.target || existing.value != ban.value);
+        bans.push(ban);
+        Ok(())
+    }
+
+    async fn remove(&self, target: BanTarget, value: &str) -> Result<Option<Ban>, StoreError> {
+        let mut bans = self.bans.write().unwrap();
+        let index = bans.iter().position(|ban| ban.target == target && ban.value == value);
+        Ok(index.map(|index| bans.remove(index)))
+    }
+
+    async fn list(&self) -> Result<Vec<Ban>, StoreError> {
+        Ok(self.bans.read().unwrap().clone())
+    }
+
+    async fn matching(&self, subjects: &[(BanTarget, String)]) -> Result<Vec<Ban>, StoreError> {
+        let bans = self.bans.read().unwrap();
+        Ok(bans.iter()
+            .filter(|ban| subjects.iter().any(|(target, value)| ban.target == *target && &ban.value == value))
+            .cloned()
+            .collect())
+    }
+
+}
+
+#[derive(Default)]
+pub struct MemoryAudit {
+    events: RwLock<Vec<AuditEvent>>,
+}
+
+#[async_trait]
+impl AuditStore for MemoryAudit {
+
+    async fn record(&self, event: AuditEvent) -> Result<(), StoreError> {
+        self.events.write().unwrap().push(event);
+        Ok(())
+    }
+
+    async fn search(&self, filter: &AuditFilter, offset: u64, limit: u64) -> Result<Vec<AuditEvent>, StoreError> {
+        let events = self.events.read().unwrap();
+        Ok(events.iter().rev()
+            .filter(|event| filter.matches(event))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use super::{AuditEvent, AuditFilter, AuditStore, Ban, BanStore, BanTarget, Entry, EntryFilter, EntryStatus, EntryUpdate, Player, PlayerStore, ScoreQuery, ScoreStore, StoreError};
+use crate::metrics::observe_db;
+
+// Wraps a store of any backend to time its operations and count their
+// errors, labelled with `label` and the name of the method.
+pub struct Metered<S: ?Sized> {
+    label: &'static str,
+    store: Arc<S>,
+}
+
+impl<S: ?Sized> Metered<S> {
+
+    pub fn new(label: &'static str, store: Arc<S>) -> Metered<S> {
+        Metered { label, store }
+    }
+
+}
+
+#[async_trait]
+impl ScoreStore for Metered<dyn ScoreStore> {
+
+    async fn insert(&self, entry: Entry) -> Result<String, StoreError> {
+        observe_db(self.label, "insert", self.store.insert(entry)).await
+    }
+
+    async fn top(&self, query: &ScoreQuery, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        observe_db(self.label, "top", self.store.top(query, offset, limit)).await
+    }
+
+    async fn count(&self, query: &ScoreQuery) -> Result<u64, StoreError> {
+        observe_db(self.label, "count", self.store.count(query)).await
+    }
+
+    async fn best_of(&self, query: &ScoreQuery, name: &str) -> Result<Option<Entry>, StoreError> {
+        observe_db(self.label, "best_of", self.store.best_of(query, name)).await
+    }
+
+    async fn count_better(&self, query: &ScoreQuery, score: i32) -> Result<u64, StoreError> {
+        observe_db(self.label, "count_better", self.store.count_better(query, score)).await
+    }
+
+    async fn count_ahead(&self, query: &ScoreQuery, entry: &Entry) -> Result<u64, StoreError> {
+        observe_db(self.label, "count_ahead", self.store.count_ahead(query, entry)).await
+    }
+
+    async fn migrate_datetimes(&self) -> Result<u64, StoreError> {
+        observe_db(self.label, "migrate_datetimes", self.store.migrate_datetimes()).await
+    }
+
+    async fn search(&self, filter: &EntryFilter, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        observe_db(self.label, "search", self.store.search(filter, offset, limit)).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Entry>, StoreError> {
+        observe_db(self.label, "get", self.store.get(id)).await
+    }
+
+    async fn update(&self, id: &str, update: &EntryUpdate) -> Result<Option<Entry>, StoreError> {
+        observe_db(self.label, "update", self.store.update(id, update)).await
+    }
+
+    async fn review(&self, id: &str, status: EntryStatus) -> Result<Option<Entry>, StoreError> {
+        observe_db(self.label, "review", self.store.review(id, status)).await
+    }
+
+    async fn save_replay(&self, id: &str, replay: Vec<u8>) -> Result<(), StoreError> {
+        observe_db(self.label, "save_replay", self.store.save_replay(id, replay)).await
+    }
+
+    async fn replay(&self, id: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        observe_db(self.label, "replay", self.store.replay(id)).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        observe_db(self.label, "delete", self.store.delete(id)).await
+    }
+
+    async fn delete_by_name(&self, name: &str) -> Result<u64, StoreError> {
+        observe_db(self.label, "delete_by_name", self.store.delete_by_name(name)).await
+    }
+
+    async fn names(&self) -> Result<Vec<String>, StoreError> {
+        observe_db(self.label, "names", self.store.names()).await
+    }
+
+    async fn shadow_name(&self, name: &str, shadow: bool) -> Result<u64, StoreError> {
+        observe_db(self.label, "shadow_name", self.store.shadow_name(name, shadow)).await
+    }
+
+    async fn shadow_player(&self, player_id: &str, shadow: bool) -> Result<u64, StoreError> {
+        observe_db(self.label, "shadow_player", self.store.shadow_player(player_id, shadow)).await
+    }
+
+    async fn shadow_ip(&self, ip_hash: &str, shadow: bool) -> Result<u64, StoreError> {
+        observe_db(self.label, "shadow_ip", self.store.shadow_ip(ip_hash, shadow)).await
+    }
+
+}
+
+#[async_trait]
+impl PlayerStore for Metered<dyn PlayerStore> {
+
+    async fn create(&self, player: Player) -> Result<bool, StoreError> {
+        observe_db(self.label, "create", self.store.create(player)).await
+    }
+
+    async fn by_token_hash(&self, token_hash: &str) -> Result<Option<Player>, StoreError> {
+        observe_db(self.label, "by_token_hash", self.store.by_token_hash(token_hash)).await
+    }
+
+    async fn by_name_key(&self, name_key: &str) -> Result<Option<Player>, StoreError> {
+        observe_db(self.label, "by_name_key", self.store.by_name_key(name_key)).await
+    }
+
+}
+
+#[async_trait]
+impl BanStore for Metered<dyn BanStore> {
+
+    async fn add(&self, ban: Ban) -> Result<(), StoreError> {
+        observe_db(self.label, "add", self.store.add(ban)).await
+    }
+
+    async fn remove(&self, target: BanTarget, value: &str) -> Result<Option<Ban>, StoreError> {
+        observe_db(self.label, "remove", self.store.remove(target, value)).await
+    }
+
+    async fn list(&self) -> Result<Vec<Ban>, StoreError> {
+        observe_db(self.label, "list", self.store.list()).await
+    }
+
+    async fn matching(&self, subjects: &[(BanTarget, String)]) -> Result<Vec<Ban>, StoreError> {
+        observe_db(self.label, "matching", self.store.matching(subjects)).await
+    }
+
+}
+
+#[async_trait]
+impl AuditStore for Metered<dyn AuditStore> {
+
+    async fn record(&self, event: AuditEvent) -> Result<(), StoreError> {
+        observe_db(self.label, "record", self.store.record(event)).await
+    }
+
+    async fn search(&self, filter: &AuditFilter, offset: u64, limit: u64) -> Result<Vec<AuditEvent>, StoreError> {
+        observe_db(self.label, "search", self.store.search(filter, offset, limit)).await
+    }
+
+}
@@ -0,0 +1,463 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, offset::Utc};
+
+mod memory;
+mod metered;
+mod mongo;
+mod sqlite;
+
+pub use memory::{MemoryAudit, MemoryBans, MemoryPlayers, MemoryStore};
+use metered::Metered;
+pub use mongo::{MongoAudit, MongoBans, MongoPlayers, MongoStore};
+pub use sqlite::{SqliteAudit, SqliteBans, SqlitePlayers, SqliteStore};
+
+// `name` is the display name; entries of registered players also carry
+// their id, anonymous ones only the name. `id` is assigned by the store on
+// insert.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<String>,
+    pub name: String,
+    pub score: i32,
+    #[serde(with = "display_datetime")]
+    pub datetime: DateTime<Utc>,
+    // Entries of shadow-banned submitters are only shown to whoever
+    // submitted them.
+    #[serde(skip)]
+    pub shadow: bool,
+    // A hash of the address the entry was submitted from, by which anonymous
+    // submitters are told apart from other viewers and IP bans find their
+    // entries. Never sent to clients.
+    #[serde(skip)]
+    pub ip_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "EntryStatus::is_published")]
+    pub status: EntryStatus,
+    #[serde(default)]
+    pub has_replay: bool,
+}
+
+// Entries held for review are stored as pending and only ranked once they
+// are published.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryStatus {
+    #[default]
+    Published,
+    Pending,
+    Rejected,
+}
+
+impl EntryStatus {
+
+    pub fn is_published(&self) -> bool {
+        *self == EntryStatus::Published
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryStatus::Published => "published",
+            EntryStatus::Pending => "pending",
+            EntryStatus::Rejected => "rejected",
+        }
+    }
+
+}
+
+// Which scores rank first on a board: higher ones for points, lower ones
+// for e.g. completion times.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Descending,
+    Ascending,
+}
+
+impl SortOrder {
+
+    // Orders two scores so that the better one comes first.
+    pub fn compare(&self, a: i32, b: i32) -> Ordering {
+        match self {
+            SortOrder::Descending => b.cmp(&a),
+            SortOrder::Ascending => a.cmp(&b),
+        }
+    }
+
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    Mongo(#[from] mongodb::error::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Blocking(#[from] actix_web::rt::task::JoinError),
+}
+
+// The time window of a query: entries submitted at or after `from` and
+// before `to`. A missing bound is unbounded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+
+    pub fn contains(&self, datetime: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| datetime >= from) && self.to.is_none_or(|to| datetime < to)
+    }
+
+}
+
+// Which entries a board query ranks. With `best_per_player` each player, or
+// name for anonymous entries, only counts once, with its best entry in the
+// range. Unpublished entries are left out, and shadowed ones unless they
+// belong to `viewer`: the id of a player, or for anonymous viewers the hash
+// of their address, which matches the anonymous entries submitted from it.
+#[derive(Debug, Clone, Default)]
+pub struct ScoreQuery {
+    pub range: TimeRange,
+    pub best_per_player: bool,
+    pub viewer: Option<String>,
+}
+
+impl ScoreQuery {
+
+    pub fn includes(&self, entry: &Entry) -> bool {
+        self.range.contains(entry.datetime)
+            && entry.status.is_published()
+            && (!entry.shadow || self.viewer.is_some() && match &entry.player_id {
+                Some(_) => entry.player_id == self.viewer,
+                None => entry.ip_hash == self.viewer,
+            })
+    }
+
+}
+
+// Conditions of an admin search; entries have to match all that are set.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    pub name: Option<String>,
+    pub range: TimeRange,
+    pub min_score: Option<i32>,
+    pub max_score: Option<i32>,
+    pub status: Option<EntryStatus>,
+}
+
+impl EntryFilter {
+
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.name.as_ref().is_none_or(|name| &entry.name == name)
+            && self.range.contains(entry.datetime)
+            && self.min_score.is_none_or(|min| entry.score >= min)
+            && self.max_score.is_none_or(|max| entry.score <= max)
+            && self.status.is_none_or(|status| entry.status == status)
+    }
+
+}
+
+// An edit of an entry; fields that are not set are kept.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct EntryUpdate {
+    pub name: Option<String>,
+    pub score: Option<i32>,
+}
+
+// Storage for a single leaderboard, which ranks its scores by the sort order
+// it was opened with.
+#[async_trait]
+pub trait ScoreStore: Send + Sync {
+    // Returns the id of the new entry.
+    async fn insert(&self, entry: Entry) -> Result<String, StoreError>;
+    // Entries in rank order, skipping the best `offset` ones. A `limit` of 0
+    // returns none, in every backend.
+    async fn top(&self, query: &ScoreQuery, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError>;
+    async fn count(&self, query: &ScoreQuery) -> Result<u64, StoreError>;
+    // The best entry submitted under `name`, newest first among equal scores.
+    async fn best_of(&self, query: &ScoreQuery, name: &str) -> Result<Option<Entry>, StoreError>;
+    // Number of entries, or players, with a strictly better score.
+    async fn count_better(&self, query: &ScoreQuery, score: i32) -> Result<u64, StoreError>;
+    // Number of entries, or players, ranked before `entry`: those with a
+    // better score and those with an equal one submitted later.
+    async fn count_ahead(&self, query: &ScoreQuery, entry: &Entry) -> Result<u64, StoreError>;
+    // Converts entries written with the old `now.to_string()` datetimes to
+    // the typed representation. Returns the number of converted entries.
+    async fn migrate_datetimes(&self) -> Result<u64, StoreError>;
+    // Entries matching `filter`, newest first. Like `top`, a `limit` of 0
+    // returns none.
+    async fn search(&self, filter: &EntryFilter, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError>;
+    async fn get(&self, id: &str) -> Result<Option<Entry>, StoreError>;
+    // Returns the updated entry, or None if there is no entry with this id.
+    async fn update(&self, id: &str, update: &EntryUpdate) -> Result<Option<Entry>, StoreError>;
+    // Publishes or rejects a pending entry, returning it, or None if there is
+    // no pending entry with this id.
+    async fn review(&self, id: &str, status: EntryStatus) -> Result<Option<Entry>, StoreError>;
+    // Stores the input replay of an entry and sets its `has_replay`. The
+    // replay is deleted along with the entry.
+    async fn save_replay(&self, id: &str, replay: Vec<u8>) -> Result<(), StoreError>;
+    async fn replay(&self, id: &str) -> Result<Option<Vec<u8>>, StoreError>;
+    async fn delete(&self, id: &str) -> Result<bool, StoreError>;
+    // Deletes every entry submitted under `name`, returning how many.
+    async fn delete_by_name(&self, name: &str) -> Result<u64, StoreError>;
+    // The distinct names entries were submitted under.
+    async fn names(&self) -> Result<Vec<String>, StoreError>;
+    // Shadows or unshadows all entries of a name, player or address hash,
+    // returning how many were changed.
+    async fn shadow_name(&self, name: &str, shadow: bool) -> Result<u64, StoreError>;
+    async fn shadow_player(&self, player_id: &str, shadow: bool) -> Result<u64, StoreError>;
+    async fn shadow_ip(&self, ip_hash: &str, shadow: bool) -> Result<u64, StoreError>;
+}
+
+// A registered player. Only a hash of the bearer token is stored; `name_key`
+// is the form of the name that has to be unique.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Player {
+    pub id: String,
+    pub name: String,
+    pub name_key: String,
+    pub token_hash: String,
+    pub created: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait PlayerStore: Send + Sync {
+    // Returns false, without storing anything, if the name is already taken.
+    async fn create(&self, player: Player) -> Result<bool, StoreError>;
+    async fn by_token_hash(&self, token_hash: &str) -> Result<Option<Player>, StoreError>;
+    async fn by_name_key(&self, name_key: &str) -> Result<Option<Player>, StoreError>;
+}
+
+// What a ban applies to. Name bans hold the folded name, so they also match
+// look-alike spellings.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BanTarget {
+    Name,
+    Player,
+    Ip,
+}
+
+impl BanTarget {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BanTarget::Name => "name",
+            BanTarget::Player => "player",
+            BanTarget::Ip => "ip",
+        }
+    }
+
+}
+
+// Banned submitters are rejected; shadow-banned ones are accepted, but their
+// entries are hidden from everyone else.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BanMode {
+    Ban,
+    Shadow,
+}
+
+impl BanMode {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BanMode::Ban => "ban",
+            BanMode::Shadow => "shadow",
+        }
+    }
+
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    pub target: BanTarget,
+    pub value: String,
+    pub mode: BanMode,
+    pub reason: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait BanStore: Send + Sync {
+    // Replaces any existing ban of the same target and value.
+    async fn add(&self, ban: Ban) -> Result<(), StoreError>;
+    async fn remove(&self, target: BanTarget, value: &str) -> Result<Option<Ban>, StoreError>;
+    async fn list(&self) -> Result<Vec<Ban>, StoreError>;
+    // The bans applying to any of `subjects`.
+    async fn matching(&self, subjects: &[(BanTarget, String)]) -> Result<Vec<Ban>, StoreError>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditKind {
+    Submission,
+    Admin,
+}
+
+impl AuditKind {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::Submission => "submission",
+            AuditKind::Admin => "admin",
+        }
+    }
+
+}
+
+// A record of a score submission or admin action. `action` is what was done,
+// e.g. "submit" or "DELETE /admin/scores/12", and `reason` why it was
+// rejected. Submissions also record what was submitted and the entry they
+// created.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEvent {
+    pub time: DateTime<Utc>,
+    pub kind: AuditKind,
+    pub game: Option<String>,
+    pub action: String,
+    pub accepted: bool,
+    pub reason: Option<String>,
+    pub entry_id: Option<String>,
+    pub name: Option<String>,
+    pub player_id: Option<String>,
+    pub score: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub client_version: Option<String>,
+}
+
+// Conditions of an audit log search; events have to match all that are set.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub kind: Option<AuditKind>,
+    pub game: Option<String>,
+    pub name: Option<String>,
+    pub player_id: Option<String>,
+    pub ip: Option<String>,
+    pub accepted: Option<bool>,
+    pub range: TimeRange,
+}
+
+impl AuditFilter {
+
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        fn optional<T: PartialEq>(wanted: &Option<T>, value: &Option<T>) -> bool {
+            wanted.is_none() || wanted == value
+        }
+        self.kind.is_none_or(|kind| event.kind == kind)
+            && optional(&self.game, &event.game)
+            && optional(&self.name, &event.name)
+            && optional(&self.player_id, &event.player_id)
+            && optional(&self.ip, &event.ip)
+            && self.accepted.is_none_or(|accepted| event.accepted == accepted)
+            && self.range.contains(event.time)
+    }
+
+}
+
+// The audit log can only be appended to.
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    async fn record(&self, event: AuditEvent) -> Result<(), StoreError>;
+    // Events matching `filter`, newest first.
+    async fn search(&self, filter: &AuditFilter, offset: u64, limit: u64) -> Result<Vec<AuditEvent>, StoreError>;
+}
+
+// Parses the `Display` output of `DateTime<Utc>` that older versions stored,
+// e.g. "2022-09-14 18:03:12.345678 UTC".
+pub fn parse_legacy_datetime(value: &str) -> Option<DateTime<Utc>> {
+    let naive = value.strip_suffix(" UTC")?;
+    NaiveDateTime::parse_from_str(naive, "%Y-%m-%d %H:%M:%S%.f")
+        .ok()
+        .map(|datetime| datetime.and_utc())
+}
+
+// Entries are sent with datetimes in the format they had before they were
+// stored typed, e.g. "2022-09-14 18:03:12.345678 UTC", which clients parse.
+// RFC 3339 is accepted too when reading them.
+mod display_datetime {
+    use chrono::{DateTime, offset::Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(datetime: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(datetime)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        super::parse_legacy_datetime(&value)
+            .or_else(|| DateTime::parse_from_rfc3339(&value).ok().map(|datetime| datetime.with_timezone(&Utc)))
+            .ok_or_else(|| D::Error::custom(format!("invalid datetime {}", value)))
+    }
+}
+
+// A connection to one of the storage backends, from which the stores of the
+// individual boards are opened. Each board gets its own collection or table,
+// players, bans and the audit log are shared by all boards. All stores are
+// metered.
+pub enum Storage {
+    Mongo(mongodb::Database),
+    Sqlite(Arc<Mutex<rusqlite::Connection>>),
+    Memory,
+}
+
+impl Storage {
+
+    pub async fn mongo(uri: &str, database: &str) -> Result<Storage, StoreError> {
+        let client_options = mongodb::options::ClientOptions::parse(uri).await?;
+        let client = mongodb::Client::with_options(client_options)?;
+        Ok(Storage::Mongo(client.database(database)))
+    }
+
+    pub fn sqlite(path: &str) -> Result<Storage, StoreError> {
+        let conn = rusqlite::Connection::open(path)?;
+        Ok(Storage::Sqlite(Arc::new(Mutex::new(conn))))
+    }
+
+    // `table` has to be a valid collection and table name; board names are
+    // restricted accordingly.
+    pub async fn scores(&self, table: &str, order: SortOrder) -> Result<Arc<dyn ScoreStore>, StoreError> {
+        let store: Arc<dyn ScoreStore> = match self {
+            Storage::Mongo(database) => Arc::new(MongoStore::open(database, table, order).await?),
+            Storage::Sqlite(conn) => Arc::new(SqliteStore::open(conn.clone(), table, order)?),
+            Storage::Memory => Arc::new(MemoryStore::new(order)),
+        };
+        Ok(Arc::new(Metered::new("scores", store)))
+    }
+
+    pub async fn players(&self) -> Result<Arc<dyn PlayerStore>, StoreError> {
+        let store: Arc<dyn PlayerStore> = match self {
+            Storage::Mongo(database) => Arc::new(MongoPlayers::open(database).await?),
+            Storage::Sqlite(conn) => Arc::new(SqlitePlayers::open(conn.clone())?),
+            Storage::Memory => Arc::new(MemoryPlayers::default()),
+        };
+        Ok(Arc::new(Metered::new("players", store)))
+    }
+
+    pub async fn bans(&self) -> Result<Arc<dyn BanStore>, StoreError> {
+        let store: Arc<dyn BanStore> = match self {
+            Storage::Mongo(database) => Arc::new(MongoBans::open(database).await?),
+            Storage::Sqlite(conn) => Arc::new(SqliteBans::open(conn.clone())?),
+            Storage::Memory => Arc::new(MemoryBans::default()),
+        };
+        Ok(Arc::new(Metered::new("bans", store)))
+    }
+
+    pub async fn audit(&self) -> Result<Arc<dyn AuditStore>, StoreError> {
+        let store: Arc<dyn AuditStore> = match self {
+            Storage::Mongo(database) => Arc::new(MongoAudit::open(database).await?),
+            Storage::Sqlite(conn) => Arc::new(SqliteAudit::open(conn.clone())?),
+            Storage::Memory => Arc::new(MemoryAudit::default()),
+        };
+        Ok(Arc::new(Metered::new("audit", store)))
+    }
+
+}
@@ -0,0 +1,693 @@
+use mongodb::{bson::{doc, oid::ObjectId, spec::BinarySubtype, Binary, Bson, Document}, Database, IndexModel, error::{ErrorKind, WriteFailure}, options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions, ReturnDocument}, Collection};
+use bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use serde::{Serialize, Deserialize};
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use chrono::{DateTime, offset::Utc};
+
+use super::{parse_legacy_datetime, AuditEvent, AuditFilter, AuditKind, AuditStore, Ban, BanMode, BanStore, BanTarget, Entry, EntryFilter, EntryStatus, EntryUpdate, Player, PlayerStore, ScoreQuery, ScoreStore, SortOrder, StoreError, TimeRange};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredEntry {
+    #[serde(rename = "_id", default, skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    player_id: Option<String>,
+    name: String,
+    score: i32,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    datetime: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    shadow: bool,
+    #[serde(default, skip_serializing_if = "EntryStatus::is_published")]
+    status: EntryStatus,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    has_replay: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ip_hash: Option<String>,
+}
+
+impl From<Entry> for StoredEntry {
+    fn from(entry: Entry) -> StoredEntry {
+        StoredEntry {
+            id: entry.id.as_deref().and_then(|id| ObjectId::parse_str(id).ok()),
+            player_id: entry.player_id,
+            name: entry.name,
+            score: entry.score,
+            datetime: entry.datetime,
+            shadow: entry.shadow,
+            status: entry.status,
+            has_replay: entry.has_replay,
+            ip_hash: entry.ip_hash,
+        }
+    }
+}
+
+impl From<StoredEntry> for Entry {
+    fn from(stored: StoredEntry) -> Entry {
+        Entry {
+            id: stored.id.map(|id| id.to_hex()),
+            player_id: stored.player_id,
+            name: stored.name,
+            score: stored.score,
+            datetime: stored.datetime,
+            shadow: stored.shadow,
+            status: stored.status,
+            has_replay: stored.has_replay,
+            ip_hash: stored.ip_hash,
+        }
+    }
+}
+
+pub struct MongoStore {
+    collection: Collection<StoredEntry>,
+    // Replays are kept apart from the entries, by the id of their entry, so
+    // ranking queries don't load them.
+    replays: Collection<Document>,
+    order: SortOrder,
+}
+
+impl MongoStore {
+
+    pub async fn open(database: &Database, name: &str, order: SortOrder) -> Result<MongoStore, StoreError> {
+        let collection = database.collection::<StoredEntry>(name);
+        let indexes = [
+            IndexModel::builder().keys(doc! {"score": direction(order), "datetime": -1}).build(),
+            IndexModel::builder().keys(doc! {"name": 1, "score": direction(order)}).build(),
+            IndexModel::builder().keys(doc! {"datetime": -1}).build(),
+        ];
+        collection.create_indexes(indexes, None).await?;
+        let replays = database.collection::<Document>(&format!("{}_replays", name));
+        Ok(MongoStore { collection, replays, order })
+    }
+
+    fn rank_sort(&self) -> Document {
+        doc! {"score": direction(self.order), "datetime": -1}
+    }
+
+    // Counts the distinct players among the entries matching `filter`.
+    async fn count_players(&self, filter: Document) -> Result<u64, StoreError> {
+        let pipeline = [
+            doc! {"$match": filter},
+            doc! {"$group": {"_id": player_key()}},
+            doc! {"$count": "count"},
+        ];
+        let mut cursor = self.collection.aggregate(pipeline, None).await?;
+        let count = match cursor.try_next().await? {
+            Some(result) => result.get_i32("count").unwrap_or(0) as u64,
+            None => 0,
+        };
+        Ok(count)
+    }
+
+}
+
+// Entries are grouped by player id and name, so anonymous entries count by
+// name and registered players by id.
+fn player_key() -> Document {
+    doc! {"player_id": "$player_id", "name": "$name"}
+}
+
+fn direction(order: SortOrder) -> i32 {
+    match order {
+        SortOrder::Descending => -1,
+        SortOrder::Ascending => 1,
+    }
+}
+
+fn better_than(order: SortOrder, score: i32) -> Document {
+    match order {
+        SortOrder::Descending => doc! {"$gt": score},
+        SortOrder::Ascending => doc! {"$lt": score},
+    }
+}
+
+// Entries stored before there were statuses have none and are published.
+fn status_filter(status: EntryStatus) -> Bson {
+    match status {
+        EntryStatus::Published => Bson::from(doc! {"$in": [Bson::Null, status.as_str()]}),
+        _ => Bson::from(status.as_str()),
+    }
+}
+
+// The entries a query ranks, before grouping by player.
+fn query_filter(query: &ScoreQuery) -> Document {
+    let mut filter = window_filter(query.range);
+    filter.insert("status", status_filter(EntryStatus::Published));
+    match &query.viewer {
+        Some(viewer) => filter.insert("$or", vec![
+            doc! {"shadow": {"$ne": true}},
+            doc! {"player_id": viewer},
+            doc! {"player_id": null, "ip_hash": viewer},
+        ]),
+        None => filter.insert("shadow", doc! {"$ne": true}),
+    };
+    filter
+}
+
+// Ids that aren't valid ObjectIds can't belong to any entry.
+fn id_filter(id: &str) -> Option<Document> {
+    ObjectId::parse_str(id).ok().map(|id| doc! {"_id": id})
+}
+
+fn search_filter(filter: &EntryFilter) -> Document {
+    let mut document = window_filter(filter.range);
+    if let Some(name) = &filter.name {
+        document.insert("name", name);
+    }
+    let mut score = Document::new();
+    if let Some(min) = filter.min_score {
+        score.insert("$gte", min);
+    }
+    if let Some(max) = filter.max_score {
+        score.insert("$lte", max);
+    }
+    if !score.is_empty() {
+        document.insert("score", score);
+    }
+    if let Some(status) = filter.status {
+        document.insert("status", status_filter(status));
+    }
+    document
+}
+
+fn bounds(range: TimeRange) -> Document {
+    let mut bounds = Document::new();
+    if let Some(from) = range.from {
+        bounds.insert("$gte", bson::DateTime::from_chrono(from));
+    }
+    if let Some(to) = range.to {
+        bounds.insert("$lt", bson::DateTime::from_chrono(to));
+    }
+    bounds
+}
+
+fn window_filter(range: TimeRange) -> Document {
+    let datetime = bounds(range);
+    match datetime.is_empty() {
+        true => doc! {},
+        false => doc! {"datetime": datetime},
+    }
+}
+
+#[async_trait]
+impl ScoreStore for MongoStore {
+
+    async fn insert(&self, entry: Entry) -> Result<String, StoreError> {
+        let result = self.collection.insert_one(StoredEntry::from(entry), None).await?;
+        Ok(result.inserted_id.as_object_id().map(|id| id.to_hex()).unwrap_or_default())
+    }
+
+    async fn top(&self, query: &ScoreQuery, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        // The driver reads a limit of 0 as no limit at all, and $limit
+        // rejects it.
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let filter = query_filter(query);
+        let scores: Vec<StoredEntry> = match query.best_per_player {
+            true => {
+                let pipeline = [
+                    doc! {"$match": filter},
+                    doc! {"$sort": self.rank_sort()},
+                    doc! {"$group": {"_id": player_key(), "best": {"$first": "$$ROOT"}}},
+                    doc! {"$replaceRoot": {"newRoot": "$best"}},
+                    doc! {"$sort": self.rank_sort()},
+                    doc! {"$skip": offset as i64},
+                    doc! {"$limit": limit as i64},
+                ];
+                let cursor = self.collection.aggregate(pipeline, None).await?;
+                cursor.with_type::<StoredEntry>().try_collect().await?
+            },
+            false => {
+                let options = FindOptions::builder()
+                    .sort(self.rank_sort())
+                    .skip(offset)
+                    .limit(limit as i64)
+                    .build();
+                self.collection.find(filter, options).await?.try_collect().await?
+            },
+        };
+        Ok(scores.into_iter().map(Entry::from).collect())
+    }
+
+    async fn count(&self, query: &ScoreQuery) -> Result<u64, StoreError> {
+        let filter = query_filter(query);
+        match query.best_per_player {
+            true => self.count_players(filter).await,
+            false => Ok(self.collection.count_documents(filter, None).await?),
+        }
+    }
+
+    async fn best_of(&self, query: &ScoreQuery, name: &str) -> Result<Option<Entry>, StoreError> {
+        let mut filter = query_filter(query);
+        filter.insert("name", name);
+        let options = FindOneOptions::builder()
+            .sort(self.rank_sort())
+            .build();
+        Ok(self.collection.find_one(filter, options).await?.map(Entry::from))
+    }
+
+    async fn count_better(&self, query: &ScoreQuery, score: i32) -> Result<u64, StoreError> {
+        let mut filter = query_filter(query);
+        filter.insert("score", better_than(self.order, score));
+        match query.best_per_player {
+            true => self.count_players(filter).await,
+            false => Ok(self.collection.count_documents(filter, None).await?),
+        }
+    }
+
+    async fn count_ahead(&self, query: &ScoreQuery, entry: &Entry) -> Result<u64, StoreError> {
+        let mut filter = query_filter(query);
+        // The viewer condition may already take the filter's $or.
+        let ahead = doc! {"$or": [
+            {"score": better_than(self.order, entry.score)},
+            {"score": entry.score, "datetime": {"$gt": bson::DateTime::from_chrono(entry.datetime)}},
+        ]};
+        filter.insert("$and", vec![ahead]);
+        match query.best_per_player {
+            true => self.count_players(filter).await,
+            false => Ok(self.collection.count_documents(filter, None).await?),
+        }
+    }
+
+    async fn migrate_datetimes(&self) -> Result<u64, StoreError> {
+        let documents = self.collection.clone_with_type::<Document>();
+        let mut cursor = documents.find(doc! {"datetime": {"$type": "string"}}, None).await?;
+        let mut migrated = 0;
+        while let Some(document) = cursor.try_next().await? {
+            let datetime = document.get_str("datetime").ok().and_then(parse_legacy_datetime);
+            let (Ok(id), Some(datetime)) = (document.get_object_id("_id"), datetime) else {
+                continue;
+            };
+            let update = doc! {"$set": {"datetime": bson::DateTime::from_chrono(datetime)}};
+            documents.update_one(doc! {"_id": id}, update, None).await?;
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
+    async fn search(&self, filter: &EntryFilter, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"datetime": -1})
+            .skip(offset)
+            .limit(limit as i64)
+            .build();
+        let entries: Vec<StoredEntry> = self.collection.find(search_filter(filter), options).await?.try_collect().await?;
+        Ok(entries.into_iter().map(Entry::from).collect())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Entry>, StoreError> {
+        let Some(filter) = id_filter(id) else {
+            return Ok(None);
+        };
+        Ok(self.collection.find_one(filter, None).await?.map(Entry::from))
+    }
+
+    async fn update(&self, id: &str, update: &EntryUpdate) -> Result<Option<Entry>, StoreError> {
+        let Some(filter) = id_filter(id) else {
+            return Ok(None);
+        };
+        let mut set = Document::new();
+        if let Some(name) = &update.name {
+            set.insert("name", name);
+        }
+        if let Some(score) = update.score {
+            set.insert("score", score);
+        }
+        if set.is_empty() {
+            return self.get(id).await;
+        }
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let entry = self.collection.find_one_and_update(filter, doc! {"$set": set}, options).await?;
+        Ok(entry.map(Entry::from))
+    }
+
+    async fn review(&self, id: &str, status: EntryStatus) -> Result<Option<Entry>, StoreError> {
+        let Some(mut filter) = id_filter(id) else {
+            return Ok(None);
+        };
+        filter.insert("status", EntryStatus::Pending.as_str());
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let update = doc! {"$set": {"status": status.as_str()}};
+        let entry = self.collection.find_one_and_update(filter, update, options).await?;
+        Ok(entry.map(Entry::from))
+    }
+
+    async fn save_replay(&self, id: &str, replay: Vec<u8>) -> Result<(), StoreError> {
+        let Some(filter) = id_filter(id) else {
+            return Ok(());
+        };
+        let replay = Binary { subtype: BinarySubtype::Generic, bytes: replay };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.replays.replace_one(filter.clone(), doc! {"_id": filter.get("_id"), "replay": replay}, options).await?;
+        self.collection.update_one(filter, doc! {"$set": {"has_replay": true}}, None).await?;
+        Ok(())
+    }
+
+    async fn replay(&self, id: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let Some(filter) = id_filter(id) else {
+            return Ok(None);
+        };
+        let stored = self.replays.find_one(filter, None).await?;
+        Ok(stored.and_then(|stored| stored.get_binary_generic("replay").ok().cloned()))
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let Some(filter) = id_filter(id) else {
+            return Ok(false);
+        };
+        self.replays.delete_one(filter.clone(), None).await?;
+        Ok(self.collection.delete_one(filter, None).await?.deleted_count > 0)
+    }
+
+    async fn delete_by_name(&self, name: &str) -> Result<u64, StoreError> {
+        let options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+        let ids: Vec<Bson> = self.collection.clone_with_type::<Document>()
+            .find(doc! {"name": name}, options).await?
+            .try_filter_map(|entry| async move { Ok(entry.get("_id").cloned()) })
+            .try_collect().await?;
+        self.replays.delete_many(doc! {"_id": {"$in": ids}}, None).await?;
+        Ok(self.collection.delete_many(doc! {"name": name}, None).await?.deleted_count)
+    }
+
+    async fn names(&self) -> Result<Vec<String>, StoreError> {
+        let names = self.collection.distinct("name", None, None).await?;
+        Ok(names.into_iter().filter_map(|name| name.as_str().map(String::from)).collect())
+    }
+
+    async fn shadow_name(&self, name: &str, shadow: bool) -> Result<u64, StoreError> {
+        let update = doc! {"$set": {"shadow": shadow}};
+        Ok(self.collection.update_many(doc! {"name": name}, update, None).await?.modified_count)
+    }
+
+    async fn shadow_player(&self, player_id: &str, shadow: bool) -> Result<u64, StoreError> {
+        let update = doc! {"$set": {"shadow": shadow}};
+        Ok(self.collection.update_many(doc! {"player_id": player_id}, update, None).await?.modified_count)
+    }
+
+    async fn shadow_ip(&self, ip_hash: &str, shadow: bool) -> Result<u64, StoreError> {
+        let update = doc! {"$set": {"shadow": shadow}};
+        Ok(self.collection.update_many(doc! {"ip_hash": ip_hash}, update, None).await?.modified_count)
+    }
+
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredPlayer {
+    #[serde(rename = "_id")]
+    id: String,
+    name: String,
+    name_key: String,
+    token_hash: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    created: DateTime<Utc>,
+}
+
+impl From<Player> for StoredPlayer {
+    fn from(player: Player) -> StoredPlayer {
+        StoredPlayer {
+            id: player.id,
+            name: player.name,
+            name_key: player.name_key,
+            token_hash: player.token_hash,
+            created: player.created,
+        }
+    }
+}
+
+impl From<StoredPlayer> for Player {
+    fn from(stored: StoredPlayer) -> Player {
+        Player {
+            id: stored.id,
+            name: stored.name,
+            name_key: stored.name_key,
+            token_hash: stored.token_hash,
+            created: stored.created,
+        }
+    }
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(&*err.kind, ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000)
+}
+
+pub struct MongoPlayers {
+    collection: Collection<StoredPlayer>,
+}
+
+impl MongoPlayers {
+
+    pub async fn open(database: &Database) -> Result<MongoPlayers, StoreError> {
+        let collection = database.collection::<StoredPlayer>("players");
+        let unique = || Some(IndexOptions::builder().unique(true).build());
+        let indexes = [
+            IndexModel::builder().keys(doc! {"name_key": 1}).options(unique()).build(),
+            IndexModel::builder().keys(doc! {"token_hash": 1}).options(unique()).build(),
+        ];
+        collection.create_indexes(indexes, None).await?;
+        Ok(MongoPlayers { collection })
+    }
+
+}
+
+#[async_trait]
+impl PlayerStore for MongoPlayers {
+
+    async fn create(&self, player: Player) -> Result<bool, StoreError> {
+        match self.collection.insert_one(StoredPlayer::from(player), None).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn by_token_hash(&self, token_hash: &str) -> Result<Option<Player>, StoreError> {
+        let player = self.collection.find_one(doc! {"token_hash": token_hash}, None).await?;
+        Ok(player.map(Player::from))
+    }
+
+    async fn by_name_key(&self, name_key: &str) -> Result<Option<Player>, StoreError> {
+        let player = self.collection.find_one(doc! {"name_key": name_key}, None).await?;
+        Ok(player.map(Player::from))
+    }
+
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredBan {
+    target: BanTarget,
+    value: String,
+    mode: BanMode,
+    reason: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    created: DateTime<Utc>,
+}
+
+impl From<Ban> for StoredBan {
+    fn from(ban: Ban) -> StoredBan {
+        StoredBan {
+            target: ban.target,
+            value: ban.value,
+            mode: ban.mode,
+            reason: ban.reason,
+            created: ban.created,
+        }
+    }
+}
+
+impl From<StoredBan> for Ban {
+    fn from(stored: StoredBan) -> Ban {
+        Ban {
+            target: stored.target,
+            value: stored.value,
+            mode: stored.mode,
+            reason: stored.reason,
+            created: stored.created,
+        }
+    }
+}
+
+fn ban_filter(target: BanTarget, value: &str) -> Document {
+    doc! {"target": target.as_str(), "value": value}
+}
+
+pub struct MongoBans {
+    collection: Collection<StoredBan>,
+}
+
+impl MongoBans {
+
+    pub async fn open(database: &Database) -> Result<MongoBans, StoreError> {
+        let collection = database.collection::<StoredBan>("bans");
+        let unique = IndexOptions::builder().unique(true).build();
+        let index = IndexModel::builder().keys(doc! {"target": 1, "value": 1}).options(unique).build();
+        collection.create_index(index, None).await?;
+        Ok(MongoBans { collection })
+    }
+
+}
+
+#[async_trait]
+impl BanStore for MongoBans {
+
+    async fn add(&self, ban: Ban) -> Result<(), StoreError> {
+        let filter = ban_filter(ban.target, &ban.value);
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.collection.replace_one(filter, StoredBan::from(ban), options).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, target: BanTarget, value: &str) -> Result<Option<Ban>, StoreError> {
+        let ban = self.collection.find_one_and_delete(ban_filter(target, value), None).await?;
+        Ok(ban.map(Ban::from))
+    }
+
+    async fn list(&self) -> Result<Vec<Ban>, StoreError> {
+        let bans: Vec<StoredBan> = self.collection.find(None, None).await?.try_collect().await?;
+        Ok(bans.into_iter().map(Ban::from).collect())
+    }
+
+    async fn matching(&self, subjects: &[(BanTarget, String)]) -> Result<Vec<Ban>, StoreError> {
+        if subjects.is_empty() {
+            return Ok(Vec::new());
+        }
+        let filters: Vec<Document> = subjects.iter().map(|(target, value)| ban_filter(*target, value)).collect();
+        let bans: Vec<StoredBan> = self.collection.find(doc! {"$or": filters}, None).await?.try_collect().await?;
+        Ok(bans.into_iter().map(Ban::from).collect())
+    }
+
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredAuditEvent {
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    time: DateTime<Utc>,
+    kind: AuditKind,
+    game: Option<String>,
+    action: String,
+    accepted: bool,
+    reason: Option<String>,
+    entry_id: Option<String>,
+    name: Option<String>,
+    player_id: Option<String>,
+    score: Option<i32>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    client_version: Option<String>,
+}
+
+impl From<AuditEvent> for StoredAuditEvent {
+    fn from(event: AuditEvent) -> StoredAuditEvent {
+        StoredAuditEvent {
+            time: event.time,
+            kind: event.kind,
+            game: event.game,
+            action: event.action,
+            accepted: event.accepted,
+            reason: event.reason,
+            entry_id: event.entry_id,
+            name: event.name,
+            player_id: event.player_id,
+            score: event.score,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            client_version: event.client_version,
+        }
+    }
+}
+
+impl From<StoredAuditEvent> for AuditEvent {
+    fn from(stored: StoredAuditEvent) -> AuditEvent {
+        AuditEvent {
+            time: stored.time,
+            kind: stored.kind,
+            game: stored.game,
+            action: stored.action,
+            accepted: stored.accepted,
+            reason: stored.reason,
+            entry_id: stored.entry_id,
+            name: stored.name,
+            player_id: stored.player_id,
+            score: stored.score,
+            ip: stored.ip,
+            user_agent: stored.user_agent,
+            client_version: stored.client_version,
+        }
+    }
+}
+
+fn audit_filter(filter: &AuditFilter) -> Document {
+    let mut document = Document::new();
+    let time = bounds(filter.range);
+    if !time.is_empty() {
+        document.insert("time", time);
+    }
+    if let Some(kind) = filter.kind {
+        document.insert("kind", kind.as_str());
+    }
+    let fields = [
+        ("game", &filter.game),
+        ("name", &filter.name),
+        ("player_id", &filter.player_id),
+        ("ip", &filter.ip),
+    ];
+    for (field, value) in fields {
+        if let Some(value) = value {
+            document.insert(field, value);
+        }
+    }
+    if let Some(accepted) = filter.accepted {
+        document.insert("accepted", accepted);
+    }
+    document
+}
+
+pub struct MongoAudit {
+    collection: Collection<StoredAuditEvent>,
+}
+
+impl MongoAudit {
+
+    pub async fn open(database: &Database) -> Result<MongoAudit, StoreError> {
+        let collection = database.collection::<StoredAuditEvent>("audit");
+        let indexes = [
+            IndexModel::builder().keys(doc! {"time": -1}).build(),
+            IndexModel::builder().keys(doc! {"name": 1, "time": -1}).build(),
+            IndexModel::builder().keys(doc! {"ip": 1, "time": -1}).build(),
+        ];
+        collection.create_indexes(indexes, None).await?;
+        Ok(MongoAudit { collection })
+    }
+
+}
+
+#[async_trait]
+impl AuditStore for MongoAudit {
+
+    async fn record(&self, event: AuditEvent) -> Result<(), StoreError> {
+        self.collection.insert_one(StoredAuditEvent::from(event), None).await?;
+        Ok(())
+    }
+
+    async fn search(&self, filter: &AuditFilter, offset: u64, limit: u64) -> Result<Vec<AuditEvent>, StoreError> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"time": -1})
+            .skip(offset)
+            .limit(limit as i64)
+            .build();
+        let events: Vec<StoredAuditEvent> = self.collection.find(audit_filter(filter), options).await?.try_collect().await?;
+        Ok(events.into_iter().map(AuditEvent::from).collect())
+    }
+
+}
@@ -0,0 +1,800 @@
+use std::sync::{Arc, Mutex};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use async_trait::async_trait;
+use actix_web::rt::task;
+use chrono::{DateTime, SecondsFormat, offset::Utc};
+
+use super::{parse_legacy_datetime, AuditEvent, AuditFilter, AuditKind, AuditStore, Ban, BanMode, BanStore, BanTarget, Entry, EntryFilter, EntryStatus, EntryUpdate, Player, PlayerStore, ScoreQuery, ScoreStore, SortOrder, StoreError, TimeRange};
+
+// Columns added after the first version of the score tables, which are added
+// to existing tables when they are opened.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("player_id", "TEXT"),
+    ("shadow", "INTEGER NOT NULL DEFAULT 0"),
+    ("status", "TEXT NOT NULL DEFAULT 'published'"),
+    ("has_replay", "INTEGER NOT NULL DEFAULT 0"),
+    ("ip_hash", "TEXT"),
+];
+
+const ENTRY_COLUMNS: &str = "player_id, name, score, datetime, shadow, status, has_replay, ip_hash";
+
+// rusqlite is synchronous, so queries run on the blocking thread pool
+// instead of stalling the actix worker.
+async fn with_conn<T, F>(conn: &Arc<Mutex<Connection>>, f: F) -> Result<T, StoreError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+{
+    let conn = conn.clone();
+    let result = task::spawn_blocking(move || f(&conn.lock().unwrap())).await?;
+    Ok(result?)
+}
+
+fn add_columns(conn: &Connection, table: &str, columns: &[(&str, &str)]) -> rusqlite::Result<()> {
+    let existing: Vec<String> = conn
+        .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (column, definition) in columns {
+        if !existing.iter().any(|name| name == column) {
+            conn.execute_batch(&format!("ALTER TABLE \"{}\" ADD COLUMN {} {}", table, column, definition))?;
+        }
+    }
+    Ok(())
+}
+
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    table: String,
+    order: SortOrder,
+}
+
+impl SqliteStore {
+
+    // Creates the table on first use. Its name is interpolated into the
+    // queries, so it must not need escaping beyond double quotes.
+    pub fn open(conn: Arc<Mutex<Connection>>, table: &str, order: SortOrder) -> Result<SqliteStore, StoreError> {
+        let direction = direction(order);
+        let locked = conn.lock().unwrap();
+        locked.execute_batch(&format!("
+            CREATE TABLE IF NOT EXISTS \"{table}\" (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                score INTEGER NOT NULL,
+                datetime TEXT NOT NULL
+            );
+        "))?;
+        add_columns(&locked, table, ADDED_COLUMNS)?;
+        locked.execute_batch(&format!("
+            CREATE INDEX IF NOT EXISTS \"{table}_by_score_{direction}\" ON \"{table}\" (score {direction}, datetime DESC);
+            CREATE INDEX IF NOT EXISTS \"{table}_by_datetime\" ON \"{table}\" (datetime);
+            CREATE INDEX IF NOT EXISTS \"{table}_by_name\" ON \"{table}\" (name, score {direction});
+            CREATE TABLE IF NOT EXISTS \"{table}_replays\" (
+                entry_id INTEGER PRIMARY KEY,
+                replay BLOB NOT NULL
+            );
+            CREATE TRIGGER IF NOT EXISTS \"{table}_delete_replay\" AFTER DELETE ON \"{table}\" BEGIN
+                DELETE FROM \"{table}_replays\" WHERE entry_id = OLD.id;
+            END;
+        "))?;
+        drop(locked);
+        Ok(SqliteStore { conn, table: table.to_string(), order })
+    }
+
+    // The entries in a query's window that its viewer may see, binding the
+    // parameters from `query_params` to ?1, ?2 and ?3.
+    fn visible(&self) -> String {
+        format!(
+            "SELECT * FROM \"{}\" WHERE datetime >= ?1 AND datetime < ?2 AND status = 'published'
+             AND (shadow = 0 OR player_id = ?3 OR (player_id IS NULL AND ip_hash = ?3))",
+            self.table,
+        )
+    }
+
+    // The entries a query ranks, as a subquery. In best-per-player mode only
+    // each player's best entry is kept; anonymous entries are told apart by
+    // name.
+    fn ranked(&self, query: &ScoreQuery) -> String {
+        let entries = self.visible();
+        match query.best_per_player {
+            true => format!(
+                "(SELECT * FROM (SELECT *, ROW_NUMBER() OVER (PARTITION BY player_id, name ORDER BY score {}, datetime DESC) AS player_rank
+                 FROM ({})) WHERE player_rank = 1)",
+                direction(self.order), entries,
+            ),
+            false => format!("({})", entries),
+        }
+    }
+
+}
+
+// SQLite has no date type, so datetimes are stored as fixed-width RFC 3339
+// UTC strings, which sort chronologically.
+fn format_datetime(datetime: DateTime<Utc>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn direction(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Descending => "DESC",
+        SortOrder::Ascending => "ASC",
+    }
+}
+
+fn better_than(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Descending => ">",
+        SortOrder::Ascending => "<",
+    }
+}
+
+// Missing bounds are replaced by strings sorting before and after any
+// formatted datetime, so every query can use the same placeholders.
+fn window_bounds(range: TimeRange) -> (String, String) {
+    (
+        range.from.map(format_datetime).unwrap_or_default(),
+        range.to.map(format_datetime).unwrap_or(String::from("~")),
+    )
+}
+
+fn query_params(query: &ScoreQuery) -> (String, String, Option<String>) {
+    let (from, to) = window_bounds(query.range);
+    (from, to, query.viewer.clone())
+}
+
+fn read_datetime(row: &Row, column: &str) -> rusqlite::Result<DateTime<Utc>> {
+    let datetime: String = row.get(column)?;
+    DateTime::parse_from_rfc3339(&datetime)
+        .map(|datetime| datetime.with_timezone(&Utc))
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+fn read_status(row: &Row) -> rusqlite::Result<EntryStatus> {
+    let status: String = row.get("status")?;
+    match status.as_str() {
+        "published" => Ok(EntryStatus::Published),
+        "pending" => Ok(EntryStatus::Pending),
+        "rejected" => Ok(EntryStatus::Rejected),
+        _ => Err(invalid_text(status)),
+    }
+}
+
+fn read_entry(row: &Row) -> rusqlite::Result<Entry> {
+    Ok(Entry {
+        id: Some(row.get::<_, i64>("id")?.to_string()),
+        player_id: row.get("player_id")?,
+        name: row.get("name")?,
+        score: row.get("score")?,
+        datetime: read_datetime(row, "datetime")?,
+        shadow: row.get("shadow")?,
+        status: read_status(row)?,
+        has_replay: row.get("has_replay")?,
+        ip_hash: row.get("ip_hash")?,
+    })
+}
+
+#[async_trait]
+impl ScoreStore for SqliteStore {
+
+    async fn insert(&self, entry: Entry) -> Result<String, StoreError> {
+        let query = format!("INSERT INTO \"{}\" ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)", self.table, ENTRY_COLUMNS);
+        with_conn(&self.conn, move |conn| {
+            conn.execute(
+                &query,
+                params![entry.player_id, entry.name, entry.score, format_datetime(entry.datetime), entry.shadow, entry.status.as_str(), entry.has_replay, entry.ip_hash],
+            )?;
+            Ok(conn.last_insert_rowid().to_string())
+        }).await
+    }
+
+    async fn top(&self, query: &ScoreQuery, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        let (from, to, viewer) = query_params(query);
+        let query = format!(
+            "SELECT * FROM {} ORDER BY score {}, datetime DESC LIMIT ?4 OFFSET ?5",
+            self.ranked(query), direction(self.order),
+        );
+        with_conn(&self.conn, move |conn| {
+            let mut statement = conn.prepare(&query)?;
+            let rows = statement.query_map(params![from, to, viewer, limit as i64, offset as i64], read_entry)?;
+            rows.collect()
+        }).await
+    }
+
+    async fn count(&self, query: &ScoreQuery) -> Result<u64, StoreError> {
+        let (from, to, viewer) = query_params(query);
+        let query = format!("SELECT COUNT(*) FROM {}", self.ranked(query));
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(&query, params![from, to, viewer], |row| row.get::<_, i64>(0))
+        }).await.map(|count| count as u64)
+    }
+
+    async fn best_of(&self, query: &ScoreQuery, name: &str) -> Result<Option<Entry>, StoreError> {
+        let (from, to, viewer) = query_params(query);
+        let name = name.to_string();
+        let query = format!(
+            "SELECT * FROM ({}) WHERE name = ?4 ORDER BY score {}, datetime DESC LIMIT 1",
+            self.visible(), direction(self.order),
+        );
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(&query, params![from, to, viewer, name], read_entry).optional()
+        }).await
+    }
+
+    async fn count_better(&self, query: &ScoreQuery, score: i32) -> Result<u64, StoreError> {
+        let (from, to, viewer) = query_params(query);
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE score {} ?4",
+            self.ranked(query), better_than(self.order),
+        );
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(
+                &query,
+                params![from, to, viewer, score],
+                |row| row.get::<_, i64>(0),
+            )
+        }).await.map(|count| count as u64)
+    }
+
+    async fn count_ahead(&self, query: &ScoreQuery, entry: &Entry) -> Result<u64, StoreError> {
+        let (from, to, viewer) = query_params(query);
+        let (score, datetime) = (entry.score, format_datetime(entry.datetime));
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE score {} ?4 OR (score = ?4 AND datetime > ?5)",
+            self.ranked(query), better_than(self.order),
+        );
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(
+                &query,
+                params![from, to, viewer, score, datetime],
+                |row| row.get::<_, i64>(0),
+            )
+        }).await.map(|count| count as u64)
+    }
+
+    async fn migrate_datetimes(&self) -> Result<u64, StoreError> {
+        let select = format!("SELECT id, datetime FROM \"{}\" WHERE datetime LIKE '% UTC'", self.table);
+        let update = format!("UPDATE \"{}\" SET datetime = ?1 WHERE id = ?2", self.table);
+        with_conn(&self.conn, move |conn| {
+            let transaction = conn.unchecked_transaction()?;
+            let legacy: Vec<(i64, String)> = transaction
+                .prepare(&select)?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let mut migrated = 0;
+            for (id, datetime) in legacy {
+                let Some(datetime) = parse_legacy_datetime(&datetime) else {
+                    continue;
+                };
+                transaction.execute(
+                    &update,
+                    params![format_datetime(datetime), id],
+                )?;
+                migrated += 1;
+            }
+            transaction.commit()?;
+            Ok(migrated)
+        }).await
+    }
+
+    async fn search(&self, filter: &EntryFilter, offset: u64, limit: u64) -> Result<Vec<Entry>, StoreError> {
+        let (from, to) = window_bounds(filter.range);
+        let (name, min_score, max_score) = (filter.name.clone(), filter.min_score, filter.max_score);
+        let status = filter.status.map(|status| status.as_str());
+        let query = format!(
+            "SELECT * FROM \"{}\" WHERE datetime >= ?1 AND datetime < ?2
+             AND (?3 IS NULL OR name = ?3) AND (?4 IS NULL OR score >= ?4) AND (?5 IS NULL OR score <= ?5)
+             AND (?6 IS NULL OR status = ?6)
+             ORDER BY datetime DESC LIMIT ?7 OFFSET ?8",
+            self.table,
+        );
+        with_conn(&self.conn, move |conn| {
+            let mut statement = conn.prepare(&query)?;
+            let rows = statement.query_map(
+                params![from, to, name, min_score, max_score, status, limit as i64, offset as i64],
+                read_entry,
+            )?;
+            rows.collect()
+        }).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Entry>, StoreError> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(None);
+        };
+        let query = format!("SELECT * FROM \"{}\" WHERE id = ?1", self.table);
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(&query, params![id], read_entry).optional()
+        }).await
+    }
+
+    async fn update(&self, id: &str, update: &EntryUpdate) -> Result<Option<Entry>, StoreError> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(None);
+        };
+        let (name, score) = (update.name.clone(), update.score);
+        let query = format!(
+            "UPDATE \"{}\" SET name = COALESCE(?2, name), score = COALESCE(?3, score) WHERE id = ?1 RETURNING *",
+            self.table,
+        );
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(&query, params![id, name, score], read_entry).optional()
+        }).await
+    }
+
+    async fn review(&self, id: &str, status: EntryStatus) -> Result<Option<Entry>, StoreError> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(None);
+        };
+        let query = format!(
+            "UPDATE \"{}\" SET status = ?2 WHERE id = ?1 AND status = 'pending' RETURNING *",
+            self.table,
+        );
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(&query, params![id, status.as_str()], read_entry).optional()
+        }).await
+    }
+
+    async fn save_replay(&self, id: &str, replay: Vec<u8>) -> Result<(), StoreError> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(());
+        };
+        let table = self.table.clone();
+        with_conn(&self.conn, move |conn| {
+            let transaction = conn.unchecked_transaction()?;
+            transaction.execute(
+                &format!("INSERT OR REPLACE INTO \"{}_replays\" (entry_id, replay) VALUES (?1, ?2)", table),
+                params![id, replay],
+            )?;
+            transaction.execute(&format!("UPDATE \"{}\" SET has_replay = 1 WHERE id = ?1", table), params![id])?;
+            transaction.commit()
+        }).await
+    }
+
+    async fn replay(&self, id: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(None);
+        };
+        let query = format!("SELECT replay FROM \"{}_replays\" WHERE entry_id = ?1", self.table);
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(&query, params![id], |row| row.get(0)).optional()
+        }).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(false);
+        };
+        let query = format!("DELETE FROM \"{}\" WHERE id = ?1", self.table);
+        with_conn(&self.conn, move |conn| {
+            conn.execute(&query, params![id])
+        }).await.map(|deleted| deleted > 0)
+    }
+
+    async fn delete_by_name(&self, name: &str) -> Result<u64, StoreError> {
+        let name = name.to_string();
+        let query = format!("DELETE FROM \"{}\" WHERE name = ?1", self.table);
+        with_conn(&self.conn, move |conn| {
+            conn.execute(&query, params![name])
+        }).await.map(|deleted| deleted as u64)
+    }
+
+    async fn names(&self) -> Result<Vec<String>, StoreError> {
+        let query = format!("SELECT DISTINCT name FROM \"{}\"", self.table);
+        with_conn(&self.conn, move |conn| {
+            let mut statement = conn.prepare(&query)?;
+            let rows = statement.query_map([], |row| row.get(0))?;
+            rows.collect()
+        }).await
+    }
+
+    async fn shadow_name(&self, name: &str, shadow: bool) -> Result<u64, StoreError> {
+        let name = name.to_string();
+        let query = format!("UPDATE \"{}\" SET shadow = ?2 WHERE name = ?1 AND shadow != ?2", self.table);
+        with_conn(&self.conn, move |conn| {
+            conn.execute(&query, params![name, shadow])
+        }).await.map(|changed| changed as u64)
+    }
+
+    async fn shadow_player(&self, player_id: &str, shadow: bool) -> Result<u64, StoreError> {
+        let player_id = player_id.to_string();
+        let query = format!("UPDATE \"{}\" SET shadow = ?2 WHERE player_id = ?1 AND shadow != ?2", self.table);
+        with_conn(&self.conn, move |conn| {
+            conn.execute(&query, params![player_id, shadow])
+        }).await.map(|changed| changed as u64)
+    }
+
+    async fn shadow_ip(&self, ip_hash: &str, shadow: bool) -> Result<u64, StoreError> {
+        let ip_hash = ip_hash.to_string();
+        let query = format!("UPDATE \"{}\" SET shadow = ?2 WHERE ip_hash = ?1 AND shadow != ?2", self.table);
+        with_conn(&self.conn, move |conn| {
+            conn.execute(&query, params![ip_hash, shadow])
+        }).await.map(|changed| changed as u64)
+    }
+
+}
+
+pub struct SqlitePlayers {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqlitePlayers {
+
+    pub fn open(conn: Arc<Mutex<Connection>>) -> Result<SqlitePlayers, StoreError> {
+        conn.lock().unwrap().execute_batch("
+            CREATE TABLE IF NOT EXISTS players (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                name_key TEXT NOT NULL UNIQUE,
+                token_hash TEXT NOT NULL UNIQUE,
+                created TEXT NOT NULL
+            );
+        ")?;
+        Ok(SqlitePlayers { conn })
+    }
+
+}
+
+fn read_player(row: &Row) -> rusqlite::Result<Player> {
+    Ok(Player {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        name_key: row.get("name_key")?,
+        token_hash: row.get("token_hash")?,
+        created: read_datetime(row, "created")?,
+    })
+}
+
+#[async_trait]
+impl PlayerStore for SqlitePlayers {
+
+    async fn create(&self, player: Player) -> Result<bool, StoreError> {
+        with_conn(&self.conn, move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO players (id, name, name_key, token_hash, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![player.id, player.name, player.name_key, player.token_hash, format_datetime(player.created)],
+            )
+        }).await.map(|inserted| inserted == 1)
+    }
+
+    async fn by_token_hash(&self, token_hash: &str) -> Result<Option<Player>, StoreError> {
+        let token_hash = token_hash.to_string();
+        with_conn(&self.conn, move |conn| {
+            conn.query_row("SELECT * FROM players WHERE token_hash = ?1", params![token_hash], read_player).optional()
+        }).await
+    }
+
+    async fn by_name_key(&self, name_key: &str) -> Result<Option<Player>, StoreError> {
+        let name_key = name_key.to_string();
+        with_conn(&self.conn, move |conn| {
+            conn.query_row("SELECT * FROM players WHERE name_key = ?1", params![name_key], read_player).optional()
+        }).await
+    }
+
+}
+
+pub struct SqliteBans {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteBans {
+
+    pub fn open(conn: Arc<Mutex<Connection>>) -> Result<SqliteBans, StoreError> {
+        conn.lock().unwrap().execute_batch("
+            CREATE TABLE IF NOT EXISTS bans (
+                target TEXT NOT NULL,
+                value TEXT NOT NULL,
+                mode TEXT NOT NULL,
+                reason TEXT,
+                created TEXT NOT NULL,
+                PRIMARY KEY (target, value)
+            );
+        ")?;
+        Ok(SqliteBans { conn })
+    }
+
+}
+
+fn invalid_text(value: String) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, format!("Unexpected value {}", value).into())
+}
+
+fn read_ban(row: &Row) -> rusqlite::Result<Ban> {
+    let target: String = row.get("target")?;
+    let target = match target.as_str() {
+        "name" => BanTarget::Name,
+        "player" => BanTarget::Player,
+        "ip" => BanTarget::Ip,
+        _ => return Err(invalid_text(target)),
+    };
+    let mode: String = row.get("mode")?;
+    let mode = match mode.as_str() {
+        "ban" => BanMode::Ban,
+        "shadow" => BanMode::Shadow,
+        _ => return Err(invalid_text(mode)),
+    };
+    Ok(Ban {
+        target,
+        value: row.get("value")?,
+        mode,
+        reason: row.get("reason")?,
+        created: read_datetime(row, "created")?,
+    })
+}
+
+#[async_trait]
+impl BanStore for SqliteBans {
+
+    async fn add(&self, ban: Ban) -> Result<(), StoreError> {
+        with_conn(&self.conn, move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO bans (target, value, mode, reason, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![ban.target.as_str(), ban.value, ban.mode.as_str(), ban.reason, format_datetime(ban.created)],
+            )
+        }).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, target: BanTarget, value: &str) -> Result<Option<Ban>, StoreError> {
+        let value = value.to_string();
+        with_conn(&self.conn, move |conn| {
+            conn.query_row(
+                "DELETE FROM bans WHERE target = ?1 AND value = ?2 RETURNING *",
+                params![target.as_str(), value],
+                read_ban,
+            ).optional()
+        }).await
+    }
+
+    async fn list(&self) -> Result<Vec<Ban>, StoreError> {
+        with_conn(&self.conn, move |conn| {
+            let mut statement = conn.prepare("SELECT * FROM bans ORDER BY created")?;
+            let rows = statement.query_map([], read_ban)?;
+            rows.collect()
+        }).await
+    }
+
+    async fn matching(&self, subjects: &[(BanTarget, String)]) -> Result<Vec<Ban>, StoreError> {
+        let subjects = subjects.to_vec();
+        with_conn(&self.conn, move |conn| {
+            let mut statement = conn.prepare("SELECT * FROM bans WHERE target = ?1 AND value = ?2")?;
+            let mut bans = Vec::new();
+            for (target, value) in subjects {
+                if let Some(ban) = statement.query_row(params![target.as_str(), value], read_ban).optional()? {
+                    bans.push(ban);
+                }
+            }
+            Ok(bans)
+        }).await
+    }
+
+}
+
+pub struct SqliteAudit {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteAudit {
+
+    pub fn open(conn: Arc<Mutex<Connection>>) -> Result<SqliteAudit, StoreError> {
+        conn.lock().unwrap().execute_batch("
+            CREATE TABLE IF NOT EXISTS audit (
+                id INTEGER PRIMARY KEY,
+                time TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                game TEXT,
+                action TEXT NOT NULL,
+                accepted INTEGER NOT NULL,
+                reason TEXT,
+                entry_id TEXT,
+                name TEXT,
+                player_id TEXT,
+                score INTEGER,
+                ip TEXT,
+                user_agent TEXT,
+                client_version TEXT
+            );
+            CREATE INDEX IF NOT EXISTS audit_by_time ON audit (time);
+            CREATE INDEX IF NOT EXISTS audit_by_name ON audit (name, time);
+            CREATE INDEX IF NOT EXISTS audit_by_ip ON audit (ip, time);
+        ")?;
+        Ok(SqliteAudit { conn })
+    }
+
+}
+
+fn read_audit_event(row: &Row) -> rusqlite::Result<AuditEvent> {
+    let kind: String = row.get("kind")?;
+    let kind = match kind.as_str() {
+        "submission" => AuditKind::Submission,
+        "admin" => AuditKind::Admin,
+        _ => return Err(invalid_text(kind)),
+    };
+    Ok(AuditEvent {
+        time: read_datetime(row, "time")?,
+        kind,
+        game: row.get("game")?,
+        action: row.get("action")?,
+        accepted: row.get("accepted")?,
+        reason: row.get("reason")?,
+        entry_id: row.get("entry_id")?,
+        name: row.get("name")?,
+        player_id: row.get("player_id")?,
+        score: row.get("score")?,
+        ip: row.get("ip")?,
+        user_agent: row.get("user_agent")?,
+        client_version: row.get("client_version")?,
+    })
+}
+
+#[async_trait]
+impl AuditStore for SqliteAudit {
+
+    async fn record(&self, event: AuditEvent) -> Result<(), StoreError> {
+        with_conn(&self.conn, move |conn| {
+            conn.execute(
+                "INSERT INTO audit (time, kind, game, action, accepted, reason, entry_id, name, player_id, score, ip, user_agent, client_version)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    format_datetime(event.time), event.kind.as_str(), event.game, event.action, event.accepted,
+                    event.reason, event.entry_id, event.name, event.player_id, event.score, event.ip,
+                    event.user_agent, event.client_version,
+                ],
+            )
+        }).await?;
+        Ok(())
+    }
+
+    async fn search(&self, filter: &AuditFilter, offset: u64, limit: u64) -> Result<Vec<AuditEvent>, StoreError> {
+        let (from, to) = window_bounds(filter.range);
+        let filter = filter.clone();
+        with_conn(&self.conn, move |conn| {
+            let mut statement = conn.prepare(
+                "SELECT * FROM audit WHERE time >= ?1 AND time < ?2
+                 AND (?3 IS NULL OR kind = ?3) AND (?4 IS NULL OR game = ?4) AND (?5 IS NULL OR name = ?5)
+                 AND (?6 IS NULL OR player_id = ?6) AND (?7 IS NULL OR ip = ?7) AND (?8 IS NULL OR accepted = ?8)
+                 ORDER BY id DESC LIMIT ?9 OFFSET ?10",
+            )?;
+            let rows = statement.query_map(
+                params![
+                    from, to, filter.kind.map(|kind| kind.as_str()), filter.game, filter.name,
+                    filter.player_id, filter.ip, filter.accepted, limit as i64, offset as i64,
+                ],
+                read_audit_event,
+            )?;
+            rows.collect()
+        }).await
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use super::*;
+
+    fn open(order: SortOrder) -> (Arc<Mutex<Connection>>, SqliteStore) {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let store = SqliteStore::open(conn.clone(), "scores", order).unwrap();
+        (conn, store)
+    }
+
+    fn entry(player_id: Option<&str>, name: &str, score: i32, minutes_ago: i64) -> Entry {
+        Entry {
+            id: None,
+            player_id: player_id.map(String::from),
+            name: name.to_string(),
+            score,
+            datetime: Utc::now() - Duration::minutes(minutes_ago),
+            shadow: false,
+            status: EntryStatus::Published,
+            has_replay: false,
+            ip_hash: None,
+        }
+    }
+
+    async fn names(store: &SqliteStore, query: &ScoreQuery) -> Vec<String> {
+        store.top(query, 0, 100).await.unwrap().into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[actix_web::test]
+    async fn ranks_by_score_and_newest_first_among_ties() {
+        let (_, store) = open(SortOrder::Descending);
+        for entry in [entry(None, "old", 10, 3), entry(None, "new", 10, 1), entry(None, "best", 20, 2), entry(None, "worst", 5, 0)] {
+            store.insert(entry).await.unwrap();
+        }
+        let query = ScoreQuery::default();
+        assert_eq!(names(&store, &query).await, ["best", "new", "old", "worst"]);
+        let page: Vec<_> = store.top(&query, 1, 2).await.unwrap().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(page, ["new", "old"]);
+        assert_eq!(store.count(&query).await.unwrap(), 4);
+        assert_eq!(store.count_better(&query, 10).await.unwrap(), 1);
+        let old = store.best_of(&query, "old").await.unwrap().unwrap();
+        assert_eq!(store.count_ahead(&query, &old).await.unwrap(), 2);
+
+        let (_, store) = open(SortOrder::Ascending);
+        for entry in [entry(None, "slow", 90, 1), entry(None, "fast", 30, 0)] {
+            store.insert(entry).await.unwrap();
+        }
+        assert_eq!(names(&store, &query).await, ["fast", "slow"]);
+        assert_eq!(store.count_better(&query, 60).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn keeps_the_best_entry_of_each_player() {
+        let (_, store) = open(SortOrder::Descending);
+        for entry in [
+            entry(Some("p1"), "ada", 10, 4),
+            entry(Some("p1"), "ada", 30, 3),
+            entry(None, "ada", 20, 2),
+            entry(None, "bob", 25, 1),
+            entry(None, "bob", 5, 0),
+        ] {
+            store.insert(entry).await.unwrap();
+        }
+        let query = ScoreQuery { best_per_player: true, ..ScoreQuery::default() };
+        let best: Vec<_> = store.top(&query, 0, 10).await.unwrap().into_iter().map(|entry| (entry.name, entry.score)).collect();
+        assert_eq!(best, [(String::from("ada"), 30), (String::from("bob"), 25), (String::from("ada"), 20)]);
+        assert_eq!(store.count(&query).await.unwrap(), 3);
+        assert_eq!(store.count_better(&query, 20).await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn windows_include_their_start_but_not_their_end() {
+        let (_, store) = open(SortOrder::Descending);
+        let start = Utc::now() - Duration::hours(1);
+        for (name, datetime) in [("before", start - Duration::milliseconds(1)), ("start", start), ("end", start + Duration::minutes(30))] {
+            store.insert(Entry { datetime, ..entry(None, name, 1, 0) }).await.unwrap();
+        }
+        let range = TimeRange { from: Some(start), to: Some(start + Duration::minutes(30)) };
+        let query = ScoreQuery { range, ..ScoreQuery::default() };
+        assert_eq!(names(&store, &query).await, ["start"]);
+        let query = ScoreQuery { range: TimeRange { from: Some(start), to: None }, ..ScoreQuery::default() };
+        assert_eq!(store.count(&query).await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn shows_shadowed_and_held_entries_only_where_they_belong() {
+        let (_, store) = open(SortOrder::Descending);
+        store.insert(Entry { shadow: true, ..entry(Some("p1"), "shadowed", 30, 2) }).await.unwrap();
+        store.insert(Entry { shadow: true, ip_hash: Some(String::from("h1")), ..entry(None, "anonymous", 25, 2) }).await.unwrap();
+        store.insert(Entry { status: EntryStatus::Pending, ..entry(None, "held", 20, 1) }).await.unwrap();
+        store.insert(entry(None, "shown", 10, 0)).await.unwrap();
+        assert_eq!(names(&store, &ScoreQuery::default()).await, ["shown"]);
+        let query = ScoreQuery { viewer: Some(String::from("p1")), ..ScoreQuery::default() };
+        assert_eq!(names(&store, &query).await, ["shadowed", "shown"]);
+        let query = ScoreQuery { viewer: Some(String::from("h1")), ..ScoreQuery::default() };
+        assert_eq!(names(&store, &query).await, ["anonymous", "shown"]);
+        assert_eq!(store.shadow_ip("h1", false).await.unwrap(), 1);
+        assert_eq!(names(&store, &ScoreQuery::default()).await, ["anonymous", "shown"]);
+    }
+
+    #[actix_web::test]
+    async fn migrates_legacy_datetimes() {
+        let (conn, store) = open(SortOrder::Descending);
+        conn.lock().unwrap().execute(
+            "INSERT INTO scores (name, score, datetime) VALUES ('legacy', 7, '2022-09-14 18:03:12.345678 UTC')",
+            [],
+        ).unwrap();
+        store.insert(entry(None, "typed", 8, 0)).await.unwrap();
+        assert_eq!(store.migrate_datetimes().await.unwrap(), 1);
+        assert_eq!(store.migrate_datetimes().await.unwrap(), 0);
+        let legacy = store.best_of(&ScoreQuery::default(), "legacy").await.unwrap().unwrap();
+        assert_eq!(format_datetime(legacy.datetime), "2022-09-14T18:03:12.345Z");
+    }
+
+    #[actix_web::test]
+    async fn updates_entries_and_deletes_their_replays_with_them() {
+        let (_, store) = open(SortOrder::Descending);
+        let id = store.insert(entry(None, "ada", 10, 0)).await.unwrap();
+        let update = EntryUpdate { name: None, score: Some(12) };
+        let updated = store.update(&id, &update).await.unwrap().unwrap();
+        assert_eq!((updated.name.as_str(), updated.score), ("ada", 12));
+        assert!(store.update("404", &update).await.unwrap().is_none());
+        store.save_replay(&id, vec![1, 2, 3]).await.unwrap();
+        assert!(store.get(&id).await.unwrap().unwrap().has_replay);
+        assert_eq!(store.replay(&id).await.unwrap(), Some(vec![1, 2, 3]));
+        assert!(store.delete(&id).await.unwrap());
+        assert_eq!(store.replay(&id).await.unwrap(), None);
+    }
+
+}
@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::LOCATION,
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use rustls::{
+    crypto::ring,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+
+fn load(cert_path: &str, key_path: &str) -> Result<CertifiedKey, String> {
+    let open = |path: &str| File::open(path).map(BufReader::new).map_err(|err| format!("Can't open {}: {}", path, err));
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Can't read {}: {}", cert_path, err))?;
+    if certs.is_empty() {
+        return Err(format!("{} holds no certificates", cert_path));
+    }
+    let key = rustls_pemfile::private_key(&mut open(key_path)?)
+        .map_err(|err| format!("Can't read {}: {}", key_path, err))?
+        .ok_or_else(|| format!("{} holds no private key", key_path))?;
+    let key = ring::sign::any_supported_type(&key).map_err(|err| format!("Can't use the key in {}: {}", key_path, err))?;
+    let certified = CertifiedKey::new(certs, key);
+    certified.keys_match().map_err(|_| format!("The key in {} doesn't belong to the certificate in {}", key_path, cert_path))?;
+    Ok(certified)
+}
+
+// Hands every new connection the current certificate, so it can be swapped
+// without restarting. Established connections keep the one they started with.
+#[derive(Debug)]
+pub struct CertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+
+    pub fn new(cert_path: &str, key_path: &str) -> Result<CertResolver, String> {
+        let current = RwLock::new(Arc::new(load(cert_path, key_path)?));
+        Ok(CertResolver { cert_path: cert_path.to_string(), key_path: key_path.to_string(), current })
+    }
+
+    // Reads the files again. On errors the current certificate stays.
+    pub fn reload(&self) -> Result<(), String> {
+        let certified = load(&self.cert_path, &self.key_path)?;
+        *self.current.write().expect("Certificate lock is never poisoned") = Arc::new(certified);
+        Ok(())
+    }
+
+    pub fn server_config(self: Arc<CertResolver>) -> ServerConfig {
+        ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("The default provider supports the default versions")
+            .with_no_client_auth()
+            .with_cert_resolver(self)
+    }
+
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().expect("Certificate lock is never poisoned").clone())
+    }
+}
+
+// Renewed certificates are picked up with `kill -HUP`.
+#[cfg(unix)]
+pub async fn reload_on_hangup(resolver: Arc<CertResolver>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = signal(SignalKind::hangup()).expect("Should be able to listen for SIGHUP");
+    while hangups.recv().await.is_some() {
+        match resolver.reload() {
+            Ok(()) => println!("Reloaded TLS certificate"),
+            Err(err) => eprintln!("Can't reload TLS certificate, keeping the current one: {}", err),
+        }
+    }
+}
+
+// The port HTTPS is served on, for redirects to it.
+pub struct HttpsPort(pub u16);
+
+// Sends plain HTTP requests to the same URL over HTTPS. 308 keeps the method
+// and body, so a submission sent to the wrong port is repeated, not lost.
+pub async fn redirect_to_https(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if req.app_config().secure() {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let port = req.app_data::<web::Data<HttpsPort>>().expect("HTTPS port is set up").0;
+    let location = {
+        let info = req.connection_info();
+        let host = info.host();
+        // Drop the HTTP port, minding bracketed IPv6 addresses.
+        let host = match host.rfind(':') {
+            Some(colon) if !host[colon..].contains(']') => &host[..colon],
+            _ => host,
+        };
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        match port {
+            443 => format!("https://{}{}", host, path),
+            _ => format!("https://{}:{}{}", host, port, path),
+        }
+    };
+    let response = HttpResponse::PermanentRedirect().insert_header((LOCATION, location)).finish();
+    Ok(req.into_response(response).map_into_right_body())
+}
@@ -0,0 +1,111 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, offset::Utc};
+use chrono_tz::Tz;
+
+use crate::store::TimeRange;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WindowError {
+    #[error("Unknown duration \"{0}\", expected daily, weekly, monthly, yearly, alltime or custom")]
+    UnknownDuration(String),
+    #[error("A custom duration needs from and/or to")]
+    MissingBounds,
+    #[error("Invalid date \"{0}\", expected RFC 3339 or YYYY-MM-DD")]
+    InvalidDate(String),
+    #[error("from should be before to")]
+    EmptyRange,
+}
+
+// The first instant of `date` in `tz`. Where midnight falls into a DST gap
+// the day starts at the first valid time after it.
+fn start_of_day(tz: Tz, date: NaiveDate) -> DateTime<Utc> {
+    let mut local = date.and_hms_opt(0, 0, 0).unwrap();
+    loop {
+        if let Some(start) = tz.from_local_datetime(&local).earliest() {
+            return start.with_timezone(&Utc);
+        }
+        local += Duration::minutes(15);
+    }
+}
+
+// Dates without a time are taken as whole days in `tz`, so `to=2022-09-30`
+// includes all of that day.
+fn parse_bound(value: &str, tz: Tz, end: bool) -> Result<DateTime<Utc>, WindowError> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| WindowError::InvalidDate(value.to_string()))?;
+    match end {
+        true => date.succ_opt()
+            .map(|next| start_of_day(tz, next))
+            .ok_or(WindowError::InvalidDate(value.to_string())),
+        false => Ok(start_of_day(tz, date)),
+    }
+}
+
+// The range between two optional bounds, e.g. from the `from` and `to`
+// query parameters.
+pub fn range(from: Option<&str>, to: Option<&str>, tz: Tz) -> Result<TimeRange, WindowError> {
+    let from = from.map(|from| parse_bound(from, tz, false)).transpose()?;
+    let to = to.map(|to| parse_bound(to, tz, true)).transpose()?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if from >= to {
+            return Err(WindowError::EmptyRange);
+        }
+    }
+    Ok(TimeRange { from, to })
+}
+
+// Resolves a board duration to the time range it covers at `now`. Calendar
+// durations start at the beginning of the current day, ISO week (Monday),
+// month or year in the board's time zone; `from` and `to` are only used by
+// custom durations.
+pub fn resolve(duration: &str, from: Option<&str>, to: Option<&str>, tz: Tz, now: DateTime<Utc>) -> Result<TimeRange, WindowError> {
+    let today = now.with_timezone(&tz).date_naive();
+    let start = match duration {
+        "alltime" => return Ok(TimeRange::default()),
+        "daily" => today,
+        "weekly" => today - Duration::days(today.weekday().num_days_from_monday() as i64),
+        "monthly" => today.with_day(1).unwrap(),
+        "yearly" => today.with_ordinal(1).unwrap(),
+        "custom" if from.is_none() && to.is_none() => return Err(WindowError::MissingBounds),
+        "custom" => return range(from, to, tz),
+        other => return Err(WindowError::UnknownDuration(other.to_string())),
+    };
+    Ok(TimeRange { from: Some(start_of_day(tz, start)), to: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn calendar_windows_start_at_local_midnight() {
+        let now = utc("2024-05-15T10:30:00Z");
+        let start = |duration, tz| resolve(duration, None, None, tz, now).unwrap().from;
+        assert_eq!(start("daily", chrono_tz::UTC), Some(utc("2024-05-15T00:00:00Z")));
+        assert_eq!(start("weekly", chrono_tz::UTC), Some(utc("2024-05-13T00:00:00Z")));
+        assert_eq!(start("monthly", chrono_tz::UTC), Some(utc("2024-05-01T00:00:00Z")));
+        assert_eq!(start("yearly", chrono_tz::UTC), Some(utc("2024-01-01T00:00:00Z")));
+        assert_eq!(start("daily", chrono_tz::Europe::Berlin), Some(utc("2024-05-14T22:00:00Z")));
+        let alltime = resolve("alltime", None, None, chrono_tz::UTC, now).unwrap();
+        assert_eq!((alltime.from, alltime.to), (None, None));
+    }
+
+    #[test]
+    fn custom_windows_take_whole_days() {
+        let now = utc("2024-05-15T10:30:00Z");
+        let range = resolve("custom", Some("2024-05-01"), Some("2024-05-02"), chrono_tz::UTC, now).unwrap();
+        assert_eq!(range.from, Some(utc("2024-05-01T00:00:00Z")));
+        assert_eq!(range.to, Some(utc("2024-05-03T00:00:00Z")));
+        assert!(matches!(resolve("custom", None, None, chrono_tz::UTC, now), Err(WindowError::MissingBounds)));
+        assert!(matches!(resolve("custom", Some("2024-05-02"), Some("2024-05-01"), chrono_tz::UTC, now), Err(WindowError::EmptyRange)));
+        assert!(matches!(resolve("custom", Some("May 1st"), None, chrono_tz::UTC, now), Err(WindowError::InvalidDate(_))));
+        assert!(matches!(resolve("hourly", None, None, chrono_tz::UTC, now), Err(WindowError::UnknownDuration(_))));
+    }
+
+}